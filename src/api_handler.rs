@@ -1,16 +1,90 @@
 use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use google_youtube3::{oauth2, YouTube};
-use google_youtube3::api::Video;
-use google_youtube3::oauth2::{AccessToken, ServiceAccountAuthenticator};
-use google_youtube3::oauth2::authenticator::Authenticator;
-use hyper::client::HttpConnector;
-use hyper_rustls::HttpsConnector;
-use reqwest::{blocking, Client, Url};
+use chrono::{DateTime, Utc};
+use reqwest::{blocking, Proxy, StatusCode, Url};
 use reqwest::blocking::Response;
-use tracing::error;
+use reqwest::header::{ETAG, IF_NONE_MATCH, RETRY_AFTER};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, warn};
 
+use crate::config::{GoogleConfig, NetworkConfig};
+use crate::{metrics, quota};
+
+// A blocking client going out through the configured proxy and source address, if any.
+pub fn http_client(network: &NetworkConfig) -> Result<blocking::Client, Box<dyn Error>> {
+    let mut builder = blocking::Client::builder();
+    if let Some(proxy) = &network.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    if let Some(address) = &network.source_address {
+        builder = builder.local_address(address.parse::<IpAddr>()?);
+    }
+    Ok(builder.build()?)
+}
+
+pub const HOLODEX_KEY_PATH: &str = "res/keys/holodex_Key.txt";
+
+// The HoloDex key, for the places that can do without it.
+pub fn holodex_key() -> Option<String> {
+    fs::read_to_string(HOLODEX_KEY_PATH).ok()
+        .and_then(|file| file.lines().next().map(|line| line.trim().to_string()))
+        .filter(|key| !key.is_empty())
+}
+
+// When HoloDex said to come back after turning a request down with a 429. Shared by every client,
+// since the limit is on the key rather than the connection.
+static HOLODEX_RETRY_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+// Retry-After is either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => (DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc) - Utc::now()).to_std().ok(),
+    }
+}
+
+// Notes a 429 from HoloDex. Returns how long it asked to be left alone, if it said.
+fn rate_limited(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let wait = retry_after(response);
+    warn!("HoloDex rate limit hit, {}.", match wait {
+        Some(wait) => format!("asked to wait {}s", wait.as_secs()),
+        None => String::from("no Retry-After given"),
+    });
+    if let Some(wait) = wait {
+        *HOLODEX_RETRY_AT.lock().unwrap() = Some(Instant::now() + wait);
+    }
+    wait
+}
+
+// How long until HoloDex can be asked again, if it's still rate limiting.
+pub fn holodex_wait() -> Option<Duration> {
+    let retry_at = (*HOLODEX_RETRY_AT.lock().unwrap())?;
+    retry_at.checked_duration_since(Instant::now()).filter(|wait| !wait.is_zero())
+}
+
+// What a /live poll came back with.
+pub enum LiveResponse {
+    // A new list, with its ETag if HoloDex gave one.
+    Changed(Value, Option<String>),
+    // Same as the last time, going by the ETag.
+    NotModified,
+    // Turned down with a 429, with how long HoloDex asked to wait.
+    RateLimited(Option<Duration>),
+}
+
+#[derive(Clone)]
 pub struct DexClient {
     client: blocking::Client,
     header: String,
@@ -18,21 +92,72 @@ pub struct DexClient {
 }
 
 impl DexClient {
-    pub fn new(header: String) -> Self {
-        let client = blocking::Client::new();
+    pub fn new(header: String, network: &NetworkConfig) -> Result<Self, Box<dyn Error>> {
+        let client = http_client(network)?;
         let endpoint = "https://holodex.net/api/v2/live";
         let params = [("type", "stream,placeholder"), ("max_upcoming_hours", "168")];
         let url = Url::parse_with_params(endpoint, params).unwrap();
 
-        DexClient {
+        Ok(DexClient {
             client,
             header,
             url,
+        })
+    }
+
+    // Polls /live, conditionally if there's an ETag from the last poll.
+    pub fn live_check(&self, etag: Option<&str>) -> Result<LiveResponse, Box<dyn Error>> {
+        self.poll(self.url.clone(), etag)
+    }
+
+    // Live and upcoming streams of just the given channels. Lighter than /live, and meant for polling
+    // often.
+    pub fn channels_check(&self, channels: &[String], etag: Option<&str>) -> Result<LiveResponse, Box<dyn Error>> {
+        let url = Url::parse_with_params("https://holodex.net/api/v2/users/live", [("channels", channels.join(","))])?;
+        self.poll(url, etag)
+    }
+
+    fn poll(&self, url: Url, etag: Option<&str>) -> Result<LiveResponse, Box<dyn Error>> {
+        let started = Instant::now();
+        let mut request = self.client.get(url).header("X-APIKEY", &self.header);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
         }
+        let response = request.send();
+        metrics::HOLODEX_LATENCY.observe(started.elapsed().as_secs_f64());
+        let result = match &response {
+            Ok(r) if r.status() == StatusCode::NOT_MODIFIED => "not_modified",
+            Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+            Ok(r) if r.status().is_success() => "success",
+            _ => "failure",
+        };
+        metrics::HOLODEX_POLLS.inc(&[("result", result)]);
+
+        let response = response?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(LiveResponse::NotModified);
+        }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Ok(LiveResponse::RateLimited(rate_limited(&response)));
+        }
+        if !response.status().is_success() {
+            return Err(format!("Bad response status: {:?}.", response.status()).into());
+        }
+        let etag = response.headers().get(ETAG).and_then(|e| e.to_str().ok()).map(str::to_string);
+        Ok(LiveResponse::Changed(response.json()?, etag))
     }
 
-    pub fn live_check(&self) -> reqwest::Result<Response> {        
-        self.client.get(self.url.clone()).header("X-APIKEY", &self.header).send()
+    // A single video, including its duration once the stream is over. Waits out a rate limit rather
+    // than failing, since callers are working through a list and would only hit it again.
+    pub fn video(&self, id: &str) -> reqwest::Result<Value> {
+        if let Some(wait) = holodex_wait() {
+            debug!("{}: Waiting {}s for the HoloDex rate limit.", id, wait.as_secs());
+            thread::sleep(wait);
+        }
+        let url = "https://holodex.net/api/v2/videos/".to_string() + id;
+        let response = self.client.get(url).header("X-APIKEY", &self.header).send()?;
+        rate_limited(&response);
+        response.error_for_status()?.json()
     }
 }
 
@@ -125,50 +250,171 @@ pub fn _dex_channels(header: &str, organization: &str, languages: Option<&str>)
     }
 }
 
-async fn get_auth() -> Authenticator<HttpsConnector<HttpConnector>> {
-    let key =
-        oauth2::read_service_account_key("res/keys/client_secret.json").await.unwrap();
-    ServiceAccountAuthenticator::builder(key).build().await.unwrap()
+pub const GOOGLE_KEY_PATH: &str = "res/keys/google_key.txt";
+
+const VIDEOS_URL: &str = "https://www.googleapis.com/youtube/v3/videos";
+// Everything post-check and the availability tracker look at. videos.list costs the same single unit
+// whatever the parts.
+const VIDEO_PARTS: &str = "snippet,liveStreamingDetails,status";
+// The most ids videos.list takes in one call.
+pub const MAX_VIDEO_IDS: usize = 50;
+
+// The pooled http client behind the YouTube client, kept for as long as the network settings it was
+// built with stay the same.
+static YOUTUBE_POOL: Mutex<Option<(String, blocking::Client)>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum YouTubeError {
+    // The API key file couldn't be read.
+    Key(io::Error),
+    // The proxy or source address couldn't be used.
+    Network(String),
+    InvalidId(String),
+    // Today's budget is used up; nothing was sent.
+    Quota(quota::Exhausted),
+    Http(reqwest::Error),
+    // Google answered with an error, e.g. a bad key or its own quota running out.
+    Api { status: u16, reason: String, message: String },
+}
+
+impl Display for YouTubeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            YouTubeError::Key(err) => write!(f, "Failed to read the Google key from {}: {}", GOOGLE_KEY_PATH, err),
+            YouTubeError::Network(err) => write!(f, "Failed to set up the Google API client: {}", err),
+            YouTubeError::InvalidId(id) => write!(f, "Invalid API target, must be an 11 character video id: {}", id),
+            YouTubeError::Quota(err) => write!(f, "{}", err),
+            YouTubeError::Http(err) => write!(f, "Google API request failed: {}", err),
+            YouTubeError::Api { status, reason, message } =>
+                write!(f, "Google API error {} ({}): {}", status, reason, message),
+        }
+    }
+}
+
+impl Error for YouTubeError {}
+
+impl From<reqwest::Error> for YouTubeError {
+    fn from(err: reqwest::Error) -> Self {
+        YouTubeError::Http(err)
+    }
+}
+
+impl From<quota::Exhausted> for YouTubeError {
+    fn from(err: quota::Exhausted) -> Self {
+        YouTubeError::Quota(err)
+    }
+}
+
+// The parts of a videos.list item that get used. Everything is optional, since which fields come back
+// depends on the parts asked for and on the video.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Video {
+    pub id: String,
+    pub snippet: Option<Snippet>,
+    pub live_streaming_details: Option<LiveStreamingDetails>,
+    pub status: Option<VideoStatus>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snippet {
+    // "live", "upcoming" or "none".
+    pub live_broadcast_content: Option<String>,
+}
+
+// Only there for streams and premieres.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveStreamingDetails {
+    // Set once the stream is over.
+    pub actual_end_time: Option<String>,
+}
+
+// There's no lifeCycleStatus here: that's on the liveBroadcast, which only the channel's owner can
+// see. An end time and the upload status between them say the same things.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStatus {
+    // "public", "unlisted" or "private".
+    pub privacy_status: Option<String>,
+    // "uploaded" or "processed" normally; "deleted", "failed" or "rejected" once it's gone.
+    pub upload_status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VideoList {
+    #[serde(default)]
+    items: Vec<Video>,
 }
 
 //Calling the official YouTube API allows checking information not found in the HoloDex API, or
 //information that is more current. (Unsure if the official API is always up-to-date itself).
-// Also, this crate this function uses involves very out of date
-pub async fn google_api(target: String) -> Result<Video, Box<dyn Error>> {
-    if target.len() != 11 {
-        return Err(Box::<dyn Error>::from("Invalid API target, must be an 11 character video id."));
+// Public videos only need an API key, so this is just a key and a pooled client.
+#[derive(Clone)]
+pub struct YouTubeClient {
+    client: blocking::Client,
+    key: String,
+    budget: u64,
+}
+
+impl YouTubeClient {
+    // The key is read each time, so a new one is picked up straight away; the connection pool is
+    // shared between every caller with the same network settings.
+    pub fn new(network: &NetworkConfig, google: &GoogleConfig) -> Result<Self, YouTubeError> {
+        let key = fs::read_to_string(GOOGLE_KEY_PATH).map_err(YouTubeError::Key)?.trim().to_string();
+        let fingerprint = format!("{:?} {:?}", network.proxy, network.source_address);
+        let mut pool = YOUTUBE_POOL.lock().unwrap();
+        let client = match pool.as_ref() {
+            Some((built_for, client)) if *built_for == fingerprint => client.clone(),
+            _ => {
+                let client = http_client(network).map_err(|err| YouTubeError::Network(err.to_string()))?;
+                *pool = Some((fingerprint, client.clone()));
+                client
+            }
+        };
+        Ok(YouTubeClient {
+            client,
+            key,
+            budget: google.daily_budget,
+        })
     }
 
-    let key = match fs::read_to_string("res/keys/google_key.txt") {
-        Ok(file) => {
-            file.trim().to_string()
+    // Looks up any number of videos, up to 50 to a call. Videos the API doesn't return (removed, or
+    // private to anyone but the owner) are simply left out.
+    pub fn videos(&self, ids: &[&str]) -> Result<Vec<Video>, YouTubeError> {
+        if let Some(id) = ids.iter().find(|id| id.len() != 11) {
+            return Err(YouTubeError::InvalidId(id.to_string()));
         }
-        Err(err) => {
-            error!("Error reading Google key: {:?}", err);
-            return Err(err.into());
+        let mut videos = Vec::new();
+        for chunk in ids.chunks(MAX_VIDEO_IDS) {
+            quota::spend("videos.list", self.budget)?;
+            let result = self.list(&chunk.join(","));
+            metrics::GOOGLE_CALLS.inc(&[("result", if result.is_ok() { "success" } else { "failure" })]);
+            videos.extend(result?);
         }
-    };
-
-    //Taken from the example from the google_youtube3 docs; untouched so I don't mess anything up.
-    //Would have preferred to use a reqwest
-    let client = hyper::Client::builder()
-        .build(hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots().unwrap()
-            .https_or_http().enable_http1().build());
-
-    let youtube = YouTube::new(client, get_auth().await);
+        Ok(videos)
+    }
 
-    let request = youtube.videos()
-        .list(&vec![String::from("snippet")])
-        .add_id(target.as_str())
-        .param("key", &*key);
+    // A single video, or None if the API doesn't return it.
+    pub fn video(&self, id: &str) -> Result<Option<Video>, YouTubeError> {
+        Ok(self.videos(&[id])?.pop())
+    }
 
-    match request.doit().await {
-        Ok(res) => {
-            Ok(res.1.items.unwrap().pop().unwrap())
-        }
-        Err(err) => {
-            Err(err.into())
+    fn list(&self, ids: &str) -> Result<Vec<Video>, YouTubeError> {
+        let response = self.client.get(VIDEOS_URL)
+            .query(&[("part", VIDEO_PARTS), ("id", ids), ("key", &self.key)])
+            .send()?;
+        let status = response.status();
+        if !status.is_success() {
+            // {"error": {"code": 403, "message": "...", "errors": [{"reason": "quotaExceeded"}]}}
+            let body = response.json::<Value>().unwrap_or_default();
+            return Err(YouTubeError::Api {
+                status: status.as_u16(),
+                reason: body["error"]["errors"][0]["reason"].as_str().unwrap_or("unknown").to_string(),
+                message: body["error"]["message"].as_str().unwrap_or_default().to_string(),
+            });
         }
+        Ok(response.json::<VideoList>()?.items)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::Value;
+use tracing::{debug, error, info, warn};
+
+use crate::api_handler::{self, DexClient, YouTubeClient};
+use crate::catalog::{Catalog, CATALOG_PATH};
+use crate::config::Config;
+use crate::control::Control;
+use crate::discovery;
+
+// How often the tracker looks for recordings that are due a check.
+const PASS_INTERVAL: Duration = Duration::from_secs(3600);
+
+// What the upstream copy of a recording looks like now.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Upstream {
+    Public,
+    Unlisted,
+    MembersOnly,
+    Private,
+    // Gone from the API entirely. Private videos look the same to anyone but the owner, so this
+    // covers those too.
+    Removed,
+}
+
+impl Upstream {
+    // Whether anyone can still watch it upstream.
+    pub fn available(&self) -> bool {
+        matches!(self, Upstream::Public | Upstream::Unlisted | Upstream::MembersOnly)
+    }
+}
+
+impl Display for Upstream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Upstream::Public => write!(f, "public"),
+            Upstream::Unlisted => write!(f, "unlisted"),
+            Upstream::MembersOnly => write!(f, "members_only"),
+            Upstream::Private => write!(f, "private"),
+            Upstream::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+// Statuses where the archived recording is the only copy left.
+pub const LOST: [&str; 2] = ["private", "removed"];
+
+// Where statuses come from. The YouTube API can tell unlisted and private apart, but costs quota and
+// can't see membership; HoloDex is free, but only knows whether it can still find the video and
+// whether it's members-only.
+pub enum Checker {
+    Google(YouTubeClient),
+    HoloDex(DexClient),
+}
+
+impl Checker {
+    pub fn new(source: &str, dex: Option<DexClient>, config: &Config) -> Result<Checker, Box<dyn Error>> {
+        match (source, dex) {
+            ("google", _) => Ok(Checker::Google(YouTubeClient::new(&config.network, &config.google)?)),
+            ("holodex", Some(dex)) => Ok(Checker::HoloDex(dex)),
+            ("holodex", None) => Err("Checking availability through HoloDex needs the HoloDex key.".into()),
+            (other, _) => Err(format!("Unknown availability source: {}", other).into()),
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        match self {
+            Checker::Google(_) => "google",
+            Checker::HoloDex(_) => "holodex",
+        }
+    }
+
+    // How many ids go into one request.
+    fn batch(&self) -> usize {
+        match self {
+            Checker::Google(_) => api_handler::MAX_VIDEO_IDS,
+            Checker::HoloDex(_) => 1,
+        }
+    }
+
+    // The status of each of the given videos. A failed request fails every video in it.
+    pub fn check(&self, ids: &[&str]) -> Vec<(String, Result<Upstream, String>)> {
+        match self {
+            // Members-only videos come back as public; nothing the API returns to an API key tells
+            // them apart, so only HoloDex reports members-only.
+            Checker::Google(youtube) => match youtube.videos(ids) {
+                Ok(videos) => ids.iter().map(|id| {
+                    let status = match videos.iter().find(|v| v.id == *id) {
+                        None => Ok(Upstream::Removed),
+                        Some(video) => match video.status.as_ref().and_then(|s| s.privacy_status.as_deref()) {
+                            Some("public") => Ok(Upstream::Public),
+                            Some("unlisted") => Ok(Upstream::Unlisted),
+                            Some("private") => Ok(Upstream::Private),
+                            status => Err(format!("Unknown privacy status: {}", status.unwrap_or("none"))),
+                        },
+                    };
+                    (id.to_string(), status)
+                }).collect(),
+                Err(err) => ids.iter().map(|id| (id.to_string(), Err(err.to_string()))).collect(),
+            },
+            Checker::HoloDex(dex) => ids.iter().map(|id| (id.to_string(), Self::holodex(dex, id))).collect(),
+        }
+    }
+
+    fn holodex(dex: &DexClient, id: &str) -> Result<Upstream, String> {
+        let video = match dex.video(id) {
+            Ok(video) => video,
+            Err(err) if err.status().is_some_and(|s| s.as_u16() == 404) => return Ok(Upstream::Removed),
+            Err(err) => return Err(err.to_string()),
+        };
+        let text = |key: &str| video.get(key).and_then(Value::as_str).map(str::to_string);
+        Ok(match (text("status").as_deref(), text("topic_id").as_deref()) {
+            (Some("missing"), _) => Upstream::Removed,
+            (_, Some("membersonly")) => Upstream::MembersOnly,
+            _ => Upstream::Public,
+        })
+    }
+}
+
+// Checks each of the given recordings and records any change. Returns how many changed.
+pub fn check_all(catalog: &Catalog, checker: &Checker, ids: &[String], delay: Duration) -> usize {
+    // Twitch and other sites don't keep VoDs the same way, so there's nothing to compare.
+    let ids: Vec<&str> = ids.iter()
+        .filter(|id| discovery::youtube_id(id).as_deref() == Some(id.as_str()))
+        .map(String::as_str)
+        .collect();
+    let mut changed = 0;
+    for batch in ids.chunks(checker.batch()) {
+        for (id, result) in checker.check(batch) {
+            match result {
+                Ok(status) => match catalog.record_status(&id, &status.to_string(), status.available(), checker.source()) {
+                    Ok(Some(Some(previous))) => {
+                        warn!("{}: Upstream status changed from {} to {}.", id, previous, status);
+                        changed += 1;
+                    }
+                    Ok(Some(None)) => debug!("{}: First upstream check: {}", id, status),
+                    Ok(None) => debug!("{}: Still {}.", id, status),
+                    Err(err) => error!("{}: Failed to record upstream status: {:?}", id, err),
+                },
+                Err(err) => warn!("{}: Upstream check failed: {}", id, err),
+            }
+        }
+        thread::sleep(delay);
+    }
+    changed
+}
+
+// Re-checks catalogued recordings in the background, each one once per interval.
+pub fn start(control: Arc<Control>) {
+    let full_config = control.config.read().unwrap().clone();
+    let config = full_config.availability.clone();
+    if !config.enabled {
+        info!("Availability tracker disabled.");
+        return;
+    }
+    let checker = match Checker::new(&config.source, control.dex.clone(), &full_config) {
+        Ok(checker) => checker,
+        Err(err) => {
+            error!("Availability tracker not started: {}", err);
+            return;
+        }
+    };
+    thread::spawn(move || loop {
+        let config = control.config.read().unwrap().availability.clone();
+        let due = Utc::now() - chrono::Duration::hours(config.interval_hours as i64);
+        let result = Catalog::open(CATALOG_PATH)
+            .and_then(|catalog| {
+                let ids = catalog.unchecked_since(due)?;
+                if !ids.is_empty() {
+                    info!("Checking {} recordings upstream.", ids.len());
+                    let changed = check_all(&catalog, &checker, &ids, Duration::from_secs(config.delay_seconds));
+                    info!("Upstream check done, {} changed.", changed);
+                }
+                Ok(())
+            });
+        if let Err(err) = result {
+            error!("Availability check failed: {:?}", err);
+        }
+        thread::sleep(PASS_INTERVAL);
+    });
+}
@@ -0,0 +1,352 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::api_handler::DexClient;
+use crate::discovery;
+use crate::manifest::Manifest;
+use crate::verify;
+
+// One row per recording, rebuilt from the files in "downloads/" whenever asked, so it can always be
+// thrown away.
+pub const CATALOG_PATH: &str = "downloads/catalog.sqlite";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS recordings (
+    id TEXT PRIMARY KEY,
+    channel TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    org TEXT,
+    title TEXT NOT NULL,
+    topic TEXT,
+    scheduled_start TEXT,
+    actual_start TEXT,
+    actual_end TEXT,
+    duration REAL,
+    size INTEGER NOT NULL,
+    path TEXT,
+    rule TEXT,
+    available INTEGER,
+    checked TEXT,
+    high_value INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS recordings_channel ON recordings (channel);
+CREATE INDEX IF NOT EXISTS recordings_start ON recordings (actual_start, scheduled_start);
+CREATE TABLE IF NOT EXISTS availability (
+    id TEXT NOT NULL,
+    status TEXT NOT NULL,
+    previous TEXT,
+    source TEXT NOT NULL,
+    at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS availability_id ON availability (id, at);
+";
+
+#[derive(Clone, Debug, Default)]
+pub struct Entry {
+    pub id: String,
+    pub channel: String,
+    pub channel_id: String,
+    pub org: Option<String>,
+    pub title: String,
+    // HoloDex's topic, e.g. "singing" or "Minecraft".
+    pub topic: Option<String>,
+    pub scheduled_start: Option<DateTime<Utc>>,
+    pub actual_start: Option<DateTime<Utc>>,
+    pub actual_end: Option<DateTime<Utc>>,
+    // What was recorded, if it's been measured, otherwise the stream's own length.
+    pub duration: Option<f64>,
+    pub size: u64,
+    pub path: Option<String>,
+    pub rule: Option<String>,
+    // Whether the video could still be found upstream when last checked.
+    pub available: Option<bool>,
+    // When the availability tracker last checked it.
+    pub checked: Option<DateTime<Utc>>,
+    // Taken down upstream while it was being recorded.
+    pub high_value: bool,
+}
+
+// Filters for a search. Text filters are substring matches, ignoring case.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub channel: Option<String>,
+    pub org: Option<String>,
+    // Matches the topic as well as the title.
+    pub title: Option<String>,
+    pub rule: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub unavailable: bool,
+    pub high_value: bool,
+    pub limit: Option<u32>,
+}
+
+pub struct Catalog {
+    connection: Connection,
+}
+
+impl Catalog {
+    pub fn open(path: &str) -> Result<Catalog, Box<dyn Error>> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(path)?;
+        // Stream threads add their recordings as they finish, so writes can overlap.
+        connection.busy_timeout(Duration::from_secs(10))?;
+        connection.execute_batch(SCHEMA)?;
+        // Catalogs from before high_value was tracked.
+        let columns: Vec<String> = connection.prepare("SELECT name FROM pragma_table_info('recordings')")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        if !columns.iter().any(|c| c == "high_value") {
+            connection.execute("ALTER TABLE recordings ADD COLUMN high_value INTEGER NOT NULL DEFAULT 0", [])?;
+        }
+        Ok(Catalog { connection })
+    }
+
+    // Scans are additive; an entry that didn't learn something (an offline scan knows nothing about
+    // the stream's topic or schedule, and a recording nothing about availability) keeps what an
+    // earlier scan or check found.
+    pub fn upsert(&self, entry: &Entry) -> rusqlite::Result<()> {
+        self.connection.execute("
+            INSERT INTO recordings (id, channel, channel_id, org, title, topic, scheduled_start,
+                actual_start, actual_end, duration, size, path, rule, available, checked, high_value)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+            ON CONFLICT (id) DO UPDATE SET
+                channel = excluded.channel, channel_id = excluded.channel_id,
+                org = COALESCE(excluded.org, recordings.org),
+                title = excluded.title,
+                topic = COALESCE(excluded.topic, recordings.topic),
+                scheduled_start = COALESCE(excluded.scheduled_start, recordings.scheduled_start),
+                actual_start = COALESCE(excluded.actual_start, recordings.actual_start),
+                actual_end = COALESCE(excluded.actual_end, recordings.actual_end),
+                duration = COALESCE(excluded.duration, recordings.duration),
+                size = excluded.size, path = excluded.path,
+                rule = COALESCE(excluded.rule, recordings.rule),
+                high_value = MAX(excluded.high_value, recordings.high_value),
+                available = COALESCE(excluded.available, recordings.available),
+                checked = COALESCE(excluded.checked, recordings.checked)",
+            params![entry.id, entry.channel, entry.channel_id, entry.org, entry.title, entry.topic,
+                entry.scheduled_start, entry.actual_start, entry.actual_end, entry.duration,
+                entry.size as i64, entry.path, entry.rule, entry.available, entry.checked,
+                entry.high_value])?;
+        Ok(())
+    }
+
+    pub fn clear(&self) -> rusqlite::Result<()> {
+        self.connection.execute("DELETE FROM recordings", [])?;
+        Ok(())
+    }
+
+    pub fn ids(&self) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self.connection.prepare("SELECT id FROM recordings ORDER BY id")?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    // Recordings not checked upstream since the given time (or ever).
+    pub fn unchecked_since(&self, time: DateTime<Utc>) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id FROM recordings WHERE checked IS NULL OR checked < ?1 ORDER BY checked")?;
+        let rows = statement.query_map([time], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    // Notes the result of an upstream check. A row is only added to the history when the status
+    // differs from the last one; returns the previous status in that case.
+    pub fn record_status(&self, id: &str, status: &str, available: bool, source: &str)
+                         -> rusqlite::Result<Option<Option<String>>> {
+        let now = Utc::now();
+        self.connection.execute("UPDATE recordings SET available = ?2, checked = ?3 WHERE id = ?1",
+            params![id, available, now])?;
+        let previous: Option<String> = self.connection.query_row(
+            "SELECT status FROM availability WHERE id = ?1 ORDER BY at DESC LIMIT 1", [id], |row| row.get(0))
+            .optional()?;
+        if previous.as_deref() == Some(status) {
+            return Ok(None);
+        }
+        self.connection.execute("INSERT INTO availability (id, status, previous, source, at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, status, previous, source, now])?;
+        Ok(Some(previous))
+    }
+
+    // Recordings whose latest upstream status is one of the given ones, with when it changed to it.
+    pub fn with_status(&self, statuses: &[&str]) -> rusqlite::Result<Vec<(Entry, String, DateTime<Utc>)>> {
+        let placeholders = vec!["?"; statuses.len()].join(", ");
+        let sql = format!("
+            SELECT recordings.*, latest.status AS upstream, latest.at AS since FROM recordings
+            JOIN (SELECT id, status, MAX(at) AS at FROM availability GROUP BY id) AS latest
+                ON latest.id = recordings.id
+            WHERE latest.status IN ({})
+            ORDER BY latest.at DESC", placeholders);
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(statuses.iter()), |row| {
+            Ok((Self::row(row)?, row.get("upstream")?, row.get("since")?))
+        })?;
+        rows.collect()
+    }
+
+    pub fn search(&self, query: &Query) -> rusqlite::Result<Vec<Entry>> {
+        let mut sql = String::from("SELECT * FROM recordings WHERE 1 = 1");
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(channel) = &query.channel {
+            sql.push_str(" AND (channel LIKE ? OR channel_id = ?)");
+            values.push(Box::new(format!("%{}%", channel)));
+            values.push(Box::new(channel.clone()));
+        }
+        if let Some(org) = &query.org {
+            sql.push_str(" AND org LIKE ?");
+            values.push(Box::new(format!("%{}%", org)));
+        }
+        if let Some(title) = &query.title {
+            sql.push_str(" AND (title LIKE ? OR topic LIKE ?)");
+            values.push(Box::new(format!("%{}%", title)));
+            values.push(Box::new(format!("%{}%", title)));
+        }
+        if let Some(rule) = &query.rule {
+            sql.push_str(" AND rule LIKE ?");
+            values.push(Box::new(format!("{}%", rule)));
+        }
+        // Timestamps are stored as text that sorts by time, so a bare date compares correctly.
+        if let Some(from) = query.from {
+            sql.push_str(" AND COALESCE(actual_start, scheduled_start) >= ?");
+            values.push(Box::new(from.to_string()));
+        }
+        if let Some(to) = query.to {
+            sql.push_str(" AND COALESCE(actual_start, scheduled_start) < ?");
+            values.push(Box::new(to.to_string()));
+        }
+        if query.unavailable {
+            sql.push_str(" AND available = 0");
+        }
+        if query.high_value {
+            sql.push_str(" AND high_value = 1");
+        }
+        sql.push_str(" ORDER BY COALESCE(actual_start, scheduled_start)");
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(values.iter()), Self::row)?;
+        rows.collect()
+    }
+
+    fn row(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+        Ok(Entry {
+            id: row.get("id")?,
+            channel: row.get("channel")?,
+            channel_id: row.get("channel_id")?,
+            org: row.get("org")?,
+            title: row.get("title")?,
+            topic: row.get("topic")?,
+            scheduled_start: row.get("scheduled_start")?,
+            actual_start: row.get("actual_start")?,
+            actual_end: row.get("actual_end")?,
+            duration: row.get("duration")?,
+            size: row.get::<_, i64>("size")? as u64,
+            path: row.get("path")?,
+            rule: row.get("rule")?,
+            available: row.get("available")?,
+            checked: row.get("checked")?,
+            high_value: row.get("high_value")?,
+        })
+    }
+}
+
+// Builds a recording's entry from its manifest, its info.json and (if given a client) HoloDex.
+pub fn entry(id: &str, dex: Option<&DexClient>) -> Entry {
+    let manifest = Manifest::load(id).unwrap_or_else(|_| Manifest { id: id.to_string(), ..Default::default() });
+    let info: Option<Value> = manifest.info_files().iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .find_map(|text| serde_json::from_str(&text).ok());
+    let info_text = |key: &str| info.as_ref()
+        .and_then(|info| info.get(key))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let files = manifest.video_files();
+
+    let mut entry = Entry {
+        id: id.to_string(),
+        channel: info_text("channel").or_else(|| info_text("uploader")).unwrap_or(manifest.channel.clone()),
+        channel_id: info_text("channel_id").unwrap_or(manifest.channel_id.clone()),
+        title: info_text("title").unwrap_or(manifest.title.clone()),
+        actual_start: info.as_ref()
+            .and_then(|info| info.get("release_timestamp").and_then(Value::as_i64))
+            .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single()),
+        duration: manifest.verification.as_ref().and_then(|v| v.duration)
+            .or_else(|| info.as_ref().and_then(|info| info.get("duration")).and_then(Value::as_f64)),
+        size: files.iter().filter_map(|f| fs::metadata(f).ok()).map(|m| m.len()).sum(),
+        path: files.first().map(|f| f.to_string_lossy().to_string()),
+        rule: Some(manifest.rule.clone()).filter(|rule| !rule.is_empty()),
+        high_value: manifest.high_value,
+        ..Default::default()
+    };
+    if entry.title.is_empty() {
+        entry.title = manifest.target.clone();
+    }
+
+    if let (Some(dex), Some(_)) = (dex, discovery::youtube_id(id)) {
+        match dex.video(id) {
+            Ok(video) => apply_holodex(&mut entry, &video),
+            Err(err) => warn!("{}: Failed to get HoloDex metadata: {:?}", id, err),
+        }
+    }
+    entry
+}
+
+fn apply_holodex(entry: &mut Entry, video: &Value) {
+    let text = |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+    let time = |key: &str| text(video, key)
+        .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+        .map(|t| t.with_timezone(&Utc));
+    if let Some(channel) = video.get("channel") {
+        entry.org = text(channel, "org");
+        if entry.channel.is_empty() {
+            entry.channel = text(channel, "english_name").or_else(|| text(channel, "name")).unwrap_or_default();
+        }
+        if entry.channel_id.is_empty() {
+            entry.channel_id = text(channel, "id").unwrap_or_default();
+        }
+    }
+    entry.topic = text(video, "topic_id");
+    entry.scheduled_start = time("start_scheduled");
+    entry.actual_start = time("start_actual").or(entry.actual_start);
+    entry.actual_end = time("end_actual");
+    if entry.duration.is_none() {
+        entry.duration = video.get("duration").and_then(Value::as_f64).filter(|d| *d > 0.0);
+    }
+    // HoloDex marks videos it can no longer find upstream as missing.
+    if let Some(status) = text(video, "status") {
+        entry.available = Some(status != "missing");
+    }
+}
+
+// Adds or refreshes one recording, e.g. once it has finished.
+pub fn index(id: &str, dex: Option<&DexClient>) -> Result<(), Box<dyn Error>> {
+    let catalog = Catalog::open(CATALOG_PATH)?;
+    catalog.upsert(&entry(id, dex))?;
+    debug!("{}: Added to the catalog.", id);
+    Ok(())
+}
+
+// Indexes everything in the archive, optionally starting from an empty catalog. Returns how many
+// recordings were indexed.
+pub fn scan(catalog: &Catalog, dex: Option<&DexClient>, rebuild: bool) -> Result<usize, Box<dyn Error>> {
+    if rebuild {
+        catalog.clear()?;
+    }
+    let ids = verify::archive_ids();
+    for id in &ids {
+        catalog.upsert(&entry(id, dex))?;
+    }
+    info!("Catalogued {} recordings.", ids.len());
+    Ok(ids.len())
+}
@@ -0,0 +1,423 @@
+// Same pyo3 lint as in stream.rs.
+#![allow(clippy::useless_conversion)]
+
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chrono::Utc;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use tracing::{debug, error, info, warn};
+
+use crate::config::ChatConfig;
+use crate::discovery;
+use crate::registry::{RecordingHandle, RecordingState};
+use crate::stream::DOWNLOAD_DIR;
+
+// How often the chat thread checks whether the stream has gone live, or the recording has ended.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+const TWITCH_IRC: &str = "irc.chat.twitch.tv:6667";
+
+// The video download's options that the chat download needs as well, so that it's signed in to the
+// same account for members-only chat and goes out through the same proxy or address.
+pub const SHARED_OPTIONS: [&str; 4] = ["cookiefile", "cookiesfrombrowser", "proxy", "source_address"];
+
+// A chat capture running next to a recording. It follows the recording rather than the download
+// attempts, so it keeps going through video restarts and only ends with the recording.
+pub struct ChatCapture {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl ChatCapture {
+    // The options are kept up to date by the StreamManager, since the credentials and proxy it uses can
+    // change between attempts.
+    pub fn spawn(target: String, handle: RecordingHandle, config: ChatConfig, options: Arc<Mutex<Py<PyDict>>>)
+                 -> ChatCapture {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let capture = Capture { target, handle, config, options, stop: thread_stop };
+            capture.run();
+        });
+        ChatCapture { stop, thread }
+    }
+
+    // Stops the capture and waits for it to write out what it has.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.thread.join().is_err() {
+            error!("Chat thread panicked.");
+        }
+    }
+}
+
+struct Capture {
+    target: String,
+    handle: RecordingHandle,
+    config: ChatConfig,
+    options: Arc<Mutex<Py<PyDict>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Capture {
+    fn run(&self) {
+        if !self.wait_live() {
+            return;
+        }
+        if let Some(id) = discovery::youtube_id(&self.target) {
+            self.youtube(&id);
+        } else if let Some(channel) = twitch_channel(&self.target) {
+            self.twitch(&channel);
+        } else {
+            info!("{}: No chat capture for this platform.", self.target);
+        }
+    }
+
+    fn ended(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || self.handle.cancelled()
+            || self.handle.recording().is_none_or(|r| r.state == RecordingState::Done)
+    }
+
+    // There's no chat to join until the stream is up, so this waits for the video download to see it
+    // live. Returns false if the recording ended first.
+    fn wait_live(&self) -> bool {
+        while !self.ended() {
+            if self.handle.progress.lock().unwrap().live_status.as_deref() == Some("is_live") {
+                return true;
+            }
+            thread::sleep(CHECK_INTERVAL);
+        }
+        false
+    }
+
+    fn retry_wait(&self) {
+        let mut waited = Duration::ZERO;
+        while waited < Duration::from_secs(self.config.retry_seconds) && !self.ended() {
+            thread::sleep(CHECK_INTERVAL);
+            waited += CHECK_INTERVAL;
+        }
+    }
+
+    // yt-dlp's live_chat "subtitle" is already JSONL, one chat action (messages, super chats,
+    // memberships and so on) per line. Each attempt writes its own file, and they're joined into one
+    // at the end.
+    fn youtube(&self, id: &str) {
+        let mut attempt = chat_attempts(id).last().map(|(n, _)| n + 1).unwrap_or(1);
+        loop {
+            info!("{}: Capturing live chat (attempt {}).", self.target, attempt);
+            match self.youtube_attempt(id, attempt) {
+                // A finished chat download means the stream ended; going again would fetch the
+                // whole replay.
+                Ok(_) => break,
+                Err(err) if self.ended() => {
+                    debug!("{}: Chat capture stopped: {}", self.target, err);
+                    break;
+                }
+                Err(err) => warn!("{}: Chat capture failed, retrying: {}", self.target, err),
+            }
+            self.retry_wait();
+            if self.ended() {
+                break;
+            }
+            attempt += 1;
+        }
+        if let Err(err) = join_youtube(id) {
+            error!("{}: Failed to join chat files: {:?}", self.target, err);
+        }
+    }
+
+    fn youtube_attempt(&self, id: &str, attempt: u32) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let opts = PyDict::new_bound(py);
+            opts.set_item("skip_download", true)?;
+            opts.set_item("writesubtitles", true)?;
+            opts.set_item("subtitleslangs", vec!["live_chat"])?;
+            opts.set_item("nopart", true)?;
+            opts.set_item("quiet", true)?;
+            let paths = PyDict::new_bound(py);
+            paths.set_item("home", DOWNLOAD_DIR)?;
+            opts.set_item("paths", paths)?;
+            let outtmpl = PyDict::new_bound(py);
+            outtmpl.set_item("subtitle", format!("%(title)s [%(id)s] (chat {}).%(ext)s", attempt))?;
+            opts.set_item("outtmpl", outtmpl)?;
+            let hook = Py::new(py, ChatHook { stop: self.stop.clone() })?;
+            opts.set_item("progress_hooks", vec![hook.getattr(py, "hook")?])?;
+            opts.update(self.options.lock().unwrap().bind(py).as_mapping())?;
+
+            let params = PyDict::new_bound(py);
+            params.set_item("params", opts)?;
+            let yt_dlp = PyModule::import_bound(py, "yt_dlp")?.getattr("YoutubeDL")?.call((), Some(&params))?;
+            yt_dlp.call_method1("download", (vec![id],))?;
+            Ok(())
+        })
+    }
+
+    // Twitch chat is plain IRC; an anonymous login can read any channel.
+    fn twitch(&self, channel: &str) {
+        let title = self.handle.recording().map(|r| r.title).unwrap_or_default();
+        let path = Path::new(DOWNLOAD_DIR).join(sanitize(&format!("{} [{}].chat.jsonl", title, self.handle.id)));
+        let mut file = match fs::create_dir_all(DOWNLOAD_DIR)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path)) {
+            Ok(file) => file,
+            Err(err) => {
+                error!("{}: Failed to open chat file {}: {:?}", self.target, path.display(), err);
+                return;
+            }
+        };
+        info!("{}: Capturing chat for #{} to {}", self.target, channel, path.display());
+        while !self.ended() {
+            match self.twitch_session(channel, &mut file) {
+                Ok(_) => debug!("{}: Twitch chat connection closed.", self.target),
+                Err(err) => warn!("{}: Twitch chat connection failed: {:?}", self.target, err),
+            }
+            if !self.ended() {
+                self.retry_wait();
+            }
+        }
+    }
+
+    fn twitch_session(&self, channel: &str, file: &mut File) -> Result<(), Box<dyn Error>> {
+        let stream = TcpStream::connect(TWITCH_IRC)?;
+        stream.set_read_timeout(Some(CHECK_INTERVAL))?;
+        let mut writer = stream.try_clone()?;
+        write!(writer, "CAP REQ :twitch.tv/tags twitch.tv/commands\r\n")?;
+        write!(writer, "PASS SCHMOOPIIE\r\nNICK justinfan{}\r\n", anonymous_number())?;
+        write!(writer, "JOIN #{}\r\n", channel)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        while !self.ended() {
+            // Partial lines are kept across read timeouts, so nothing is lost between checks.
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) if !line.ends_with(b"\n") => continue,
+                Ok(_) => {}
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                Err(err) => return Err(err.into()),
+            }
+            let text = String::from_utf8_lossy(&line).trim_end().to_string();
+            line.clear();
+            let message = match IrcMessage::parse(&text) {
+                Some(message) => message,
+                None => continue,
+            };
+            match message.command.as_str() {
+                "PING" => write!(writer, "PONG :{}\r\n", message.trailing.as_deref().unwrap_or("tmi.twitch.tv"))?,
+                // Twitch asks clients to reconnect before restarting a server.
+                "RECONNECT" => return Ok(()),
+                "PRIVMSG" | "USERNOTICE" | "CLEARCHAT" | "CLEARMSG" => {
+                    writeln!(file, "{}", message.to_json())?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Raising from a progress hook is the only way to stop yt-dlp mid-download, same as for the video.
+#[pyclass]
+struct ChatHook {
+    stop: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl ChatHook {
+    #[pyo3(signature = (* _args, * * _kwargs))]
+    fn hook(&self, _args: &Bound<'_, PyTuple>, _kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+        if self.stop.load(Ordering::Relaxed) {
+            return Err(PyRuntimeError::new_err("Chat capture stopped."));
+        }
+        Ok(())
+    }
+}
+
+// The channel name out of a twitch.tv url, if it is one.
+fn twitch_channel(target: &str) -> Option<String> {
+    let url = Url::parse(target).ok()?;
+    if !url.host_str()?.ends_with("twitch.tv") {
+        return None;
+    }
+    let channel = url.path_segments()?.next()?.to_lowercase();
+    (!channel.is_empty() && channel != "videos").then_some(channel)
+}
+
+// Attempt files yt-dlp has written for a video, by attempt number.
+fn chat_attempts(id: &str) -> Vec<(u32, PathBuf)> {
+    let tag = format!("[{}] (chat ", id);
+    let mut attempts: Vec<(u32, PathBuf)> = fs::read_dir(DOWNLOAD_DIR).into_iter().flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_string_lossy().to_string();
+            let number = name.split_once(&tag)?.1.split_once(')')?.0.parse().ok()?;
+            name.ends_with(".live_chat.json").then_some((number, path))
+        })
+        .collect();
+    attempts.sort();
+    attempts
+}
+
+// Appends the attempt files, in order, to a single "<title> [id].chat.jsonl" and removes them.
+fn join_youtube(id: &str) -> Result<(), Box<dyn Error>> {
+    let attempts = chat_attempts(id);
+    let Some((_, first)) = attempts.first() else {
+        return Ok(());
+    };
+    let name = first.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let prefix = name.split(" (chat ").next().unwrap_or_default();
+    let path = first.with_file_name(format!("{}.chat.jsonl", prefix));
+    let mut output = OpenOptions::new().create(true).append(true).open(&path)?;
+    for (_, attempt) in &attempts {
+        io::copy(&mut File::open(attempt)?, &mut output)?;
+        fs::remove_file(attempt)?;
+    }
+    info!("{}: Chat saved to {}", id, path.display());
+    Ok(())
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars().map(|c| if matches!(c, '/' | '\\' | '\0') { '_' } else { c }).collect()
+}
+
+// Anonymous Twitch logins are "justinfan" followed by any number.
+fn anonymous_number() -> u32 {
+    (Utc::now().timestamp_subsec_nanos() % 90000) + 10000
+}
+
+// Just enough IRC to pull Twitch chat apart: "@tags :prefix COMMAND params :trailing".
+struct IrcMessage {
+    tags: Map<String, Value>,
+    user: Option<String>,
+    command: String,
+    params: Vec<String>,
+    trailing: Option<String>,
+}
+
+impl IrcMessage {
+    fn parse(line: &str) -> Option<IrcMessage> {
+        let mut rest = line;
+        let mut tags = Map::new();
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let (raw, remainder) = stripped.split_once(' ')?;
+            for tag in raw.split(';') {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                tags.insert(key.to_string(), Value::String(unescape_tag(value)));
+            }
+            rest = remainder;
+        }
+        let mut user = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (prefix, remainder) = stripped.split_once(' ')?;
+            user = prefix.split_once('!').map(|(nick, _)| nick.to_string());
+            rest = remainder;
+        }
+        let (rest, trailing) = match rest.split_once(" :") {
+            Some((rest, trailing)) => (rest, Some(trailing.to_string())),
+            None => (rest, None),
+        };
+        let mut parts = rest.split_whitespace();
+        let command = parts.next()?.to_string();
+        let params = parts.map(str::to_string).collect();
+        Some(IrcMessage { tags, user, command, params, trailing })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "time": Utc::now(),
+            "command": self.command,
+            "channel": self.params.first(),
+            "user": self.user,
+            "message": self.trailing,
+            "tags": self.tags,
+        })
+    }
+}
+
+fn unescape_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_privmsg() {
+        let line = "@badge-info=;color=#FF0000;display-name=Some\\sOne;emotes=;id=abc;tmi-sent-ts=1700000000000 \
+            :someone!someone@someone.tmi.twitch.tv PRIVMSG #channel :hello there :) how's it going";
+        let message = IrcMessage::parse(line).unwrap();
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.user.as_deref(), Some("someone"));
+        assert_eq!(message.params, ["#channel"]);
+        assert_eq!(message.trailing.as_deref(), Some("hello there :) how's it going"));
+        assert_eq!(message.tags["display-name"], "Some One");
+        assert_eq!(message.tags["color"], "#FF0000");
+        assert_eq!(message.tags["badge-info"], "");
+    }
+
+    #[test]
+    fn parses_without_tags_or_prefix() {
+        let ping = IrcMessage::parse("PING :tmi.twitch.tv").unwrap();
+        assert_eq!(ping.command, "PING");
+        assert!(ping.tags.is_empty());
+        assert_eq!(ping.user, None);
+        assert!(ping.params.is_empty());
+        assert_eq!(ping.trailing.as_deref(), Some("tmi.twitch.tv"));
+
+        // The server's own prefix has no nick in it.
+        let welcome = IrcMessage::parse(":tmi.twitch.tv 001 justinfan123 :Welcome, GLHF!").unwrap();
+        assert_eq!(welcome.command, "001");
+        assert_eq!(welcome.user, None);
+        assert_eq!(welcome.params, ["justinfan123"]);
+    }
+
+    #[test]
+    fn parses_without_trailing() {
+        let message = IrcMessage::parse(":someone!someone@someone.tmi.twitch.tv JOIN #channel").unwrap();
+        assert_eq!(message.command, "JOIN");
+        assert_eq!(message.params, ["#channel"]);
+        assert_eq!(message.trailing, None);
+    }
+
+    #[test]
+    fn rejects_incomplete_lines() {
+        assert!(IrcMessage::parse("").is_none());
+        assert!(IrcMessage::parse("@tags-only").is_none());
+        assert!(IrcMessage::parse(":prefix-only").is_none());
+    }
+
+    #[test]
+    fn unescapes_tags() {
+        assert_eq!(unescape_tag("a\\sb\\:c\\\\d\\ne\\r"), "a b;c\\d\ne\r");
+        assert_eq!(unescape_tag("trailing\\"), "trailing");
+        assert_eq!(unescape_tag("\\x"), "x");
+    }
+}
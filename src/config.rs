@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::notify::SinkConfig;
+
+// Optional settings file. Everything in it has a default, so a missing file is fine; the required
+// keys and lists are still separate files, as described in the ReadMe.
+pub const CONFIG_PATH: &str = "res/config.toml";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub control: ControlConfig,
+    pub watchdog: WatchdogConfig,
+    pub concat: ConcatConfig,
+    pub postprocess: PostProcessConfig,
+    pub verify: VerifyConfig,
+    pub chat: ChatConfig,
+    pub availability: AvailabilityConfig,
+    pub notify: NotifyConfig,
+    pub hooks: HooksConfig,
+    pub cookies: CookieConfig,
+    pub membership: MembershipConfig,
+    pub credentials: HashMap<String, CredentialProfile>,
+    pub network: NetworkConfig,
+    pub google: GoogleConfig,
+    pub discovery: DiscoveryConfig,
+}
+
+// The control server is only meant to be reached from the same machine, hence localhost or a unix
+// socket. The socket takes priority if both are set.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub unix_socket: Option<String>,
+    // If set, API requests need an "Authorization: Bearer <token>" header. Required to bind anywhere
+    // but localhost.
+    pub token: Option<String>,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            enabled: true,
+            address: String::from("127.0.0.1:8787"),
+            unix_socket: None,
+            token: None,
+        }
+    }
+}
+
+// Restarts a live recording that stops receiving data without yt-dlp noticing.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    pub stall_seconds: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        // Needs to be comfortably longer than the socket_timeout, or the watchdog will be fighting
+        // yt-dlp's own retries.
+        WatchdogConfig {
+            enabled: true,
+            stall_seconds: 180,
+        }
+    }
+}
+
+// Joins the parts of a recording that had to be restarted into one file, once it's finished.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ConcatConfig {
+    pub enabled: bool,
+    // Only ever done after the joined file has been checked against the parts.
+    pub delete_parts: bool,
+}
+
+// Saves live chat next to the recording, as "<title> [id].chat.jsonl". YouTube chat comes from
+// yt-dlp's live_chat subtitle, Twitch chat from its IRC server.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub enabled: bool,
+    // Wait before reconnecting after the chat capture fails mid-stream.
+    pub retry_seconds: u64,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        ChatConfig {
+            enabled: false,
+            retry_seconds: 15,
+        }
+    }
+}
+
+// Cookies for membership streams. Either a Netscape cookie file, as described in the yt-dlp ReadMe,
+// or a browser to read them from, which stays signed in far longer than an exported file does.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CookieConfig {
+    pub file: String,
+    // yt-dlp's cookiesfrombrowser, as "browser" or "browser:profile", e.g.
+    // "firefox:/home/pi/.mozilla/firefox/abcd1234.default". Takes priority over the file.
+    pub browser: Option<String>,
+    // Alert this many days before the login cookies run out.
+    pub warn_days: u64,
+    // How often to re-check the cookies while running.
+    pub check_minutes: u64,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            file: String::from("res/cookies.txt"),
+            browser: None,
+            warn_days: 3,
+            check_minutes: 60,
+        }
+    }
+}
+
+// Channels we hold memberships for, and the credentials that have access to each. Members-only
+// streams from any other channel are skipped rather than attempted.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MembershipConfig {
+    pub channels: HashMap<String, MembershipChannel>,
+}
+
+// A channel's own cookie file or browser, and/or named [credentials] profiles, tried in that order
+// until one gets in. Leaving them all out uses the [cookies] ones.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MembershipChannel {
+    pub file: Option<String>,
+    pub browser: Option<String>,
+    pub profiles: Vec<String>,
+}
+
+// A named set of credentials, for memberships held on different Google accounts.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CredentialProfile {
+    pub file: Option<String>,
+    pub browser: Option<String>,
+    // Passed to yt-dlp along with the cookies, for an account that should always be seen from the
+    // same place.
+    pub proxy: Option<String>,
+}
+
+// Credentials resolved down to what yt-dlp needs.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub name: String,
+    pub cookies: CookieConfig,
+    pub proxy: Option<String>,
+}
+
+impl Credentials {
+    fn new(name: &str, file: &Option<String>, browser: &Option<String>, proxy: &Option<String>,
+           default: &CookieConfig) -> Credentials {
+        Credentials {
+            name: name.to_string(),
+            cookies: CookieConfig {
+                file: file.clone().unwrap_or_default(),
+                browser: browser.clone(),
+                ..default.clone()
+            },
+            proxy: proxy.clone(),
+        }
+    }
+}
+
+// How requests leave the machine. The top-level proxy and source address apply to everything,
+// including the HoloDex and Google API clients; channels and rules can send their recordings
+// elsewhere, e.g. to spread concurrent downloads over several addresses.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub proxy: Option<String>,
+    pub source_address: Option<String>,
+    // Geo-restricted streams are retried once through this.
+    pub geo_proxy: Option<String>,
+    // Keyed by channel id.
+    pub channels: HashMap<String, Route>,
+    // Keyed by rule, e.g. "archive", "keyword" (or "keyword:<word>"), "unarchived" or "manual".
+    pub rules: HashMap<String, Route>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Route {
+    pub proxy: Option<String>,
+    pub source_address: Option<String>,
+}
+
+impl NetworkConfig {
+    // Where a recording's downloads go: the channel's route, else the rule's, else the default. Each
+    // setting falls back separately.
+    pub fn route(&self, channel_id: &str, rule: &str) -> Route {
+        let kind = rule.split(':').next().unwrap_or(rule);
+        let routes = [self.channels.get(channel_id), self.rules.get(rule), self.rules.get(kind)];
+        let pick = |get: fn(&Route) -> &Option<String>| routes.iter().flatten()
+            .find_map(|route| get(route).clone());
+        Route {
+            proxy: pick(|r| &r.proxy).or(self.proxy.clone()),
+            source_address: pick(|r| &r.source_address).or(self.source_address.clone()),
+        }
+    }
+}
+
+// Limits on the YouTube Data API, which post_check uses to confirm a stream has really ended.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GoogleConfig {
+    // Quota units to spend per day (Pacific time, like Google's own reset).
+    pub daily_budget: u64,
+    // How post_check tells whether a stream is still live once the budget is gone: "holodex" (the
+    // /videos endpoint), "ytdlp" (a metadata-only extraction) or "none".
+    pub fallback: String,
+}
+
+impl Default for GoogleConfig {
+    fn default() -> Self {
+        // A little under Google's default 10,000 a day, for anything else using the same key.
+        GoogleConfig {
+            daily_budget: 9000,
+            fallback: String::from("holodex"),
+        }
+    }
+}
+
+// How often the discovery loop polls HoloDex. "Our" channels are the ones in the archive and check
+// lists.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    // Between full /live polls, usually.
+    pub interval_seconds: u64,
+    // Between full polls from near_start_minutes before one of our channels' scheduled starts until
+    // as long after it, to catch waiting rooms and late starts.
+    pub near_start_seconds: u64,
+    pub near_start_minutes: u64,
+    // Between full polls once none of our channels has been live for idle_hours, or has anything
+    // scheduled in the next idle_hours, e.g. overnight.
+    pub idle_seconds: u64,
+    pub idle_hours: u64,
+    // Channels also polled on their own, through HoloDex's per-channel endpoint.
+    pub priority_channels: Vec<String>,
+    pub priority_seconds: u64,
+    // Most HoloDex requests in any hour, all of the above together. Polls wait for room once it's used.
+    pub hourly_budget: u32,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            interval_seconds: 120,
+            near_start_seconds: 30,
+            near_start_minutes: 10,
+            idle_seconds: 600,
+            idle_hours: 3,
+            priority_channels: Vec::new(),
+            priority_seconds: 60,
+            hourly_budget: 180,
+        }
+    }
+}
+
+// Checks finished recordings are complete and readable. Also used by the verify subcommand.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct VerifyConfig {
+    // Runs on each finished recording, before post-processing.
+    pub enabled: bool,
+    // Decodes every frame rather than just reading the packets through. Catches more, but takes
+    // about as long as a transcode.
+    pub full_decode: bool,
+    // How far short of the stream's length a recording can be before it counts as truncated.
+    pub tolerance_seconds: f64,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        VerifyConfig {
+            enabled: true,
+            full_decode: false,
+            tolerance_seconds: 60.0,
+        }
+    }
+}
+
+// Where to send notifications about recordings and the recorder itself. Nothing is sent without any
+// sinks.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub sinks: Vec<SinkConfig>,
+    // Free space on the download disk below which a disk_low event is sent.
+    pub disk_low_gb: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            sinks: Vec::new(),
+            disk_low_gb: 20,
+        }
+    }
+}
+
+// User scripts run on recording events, as a program followed by its arguments. Each gets the
+// recording's details as JSON on stdin and as AKASHIC_* environment variables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub on_start: Vec<String>,
+    pub on_finish: Vec<String>,
+    pub on_fail: Vec<String>,
+    pub on_postprocessed: Vec<String>,
+}
+
+// Periodically re-checks catalogued recordings upstream, to catch VoDs that have been privated or
+// taken down since.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AvailabilityConfig {
+    pub enabled: bool,
+    // "holodex" or "google". Google can tell unlisted and private apart, but costs API quota.
+    pub source: String,
+    // How long between checks of the same recording.
+    pub interval_hours: u64,
+    // Pause between requests, to go easy on the API.
+    pub delay_seconds: u64,
+}
+
+impl Default for AvailabilityConfig {
+    fn default() -> Self {
+        AvailabilityConfig {
+            enabled: true,
+            source: String::from("holodex"),
+            interval_hours: 24,
+            delay_seconds: 2,
+        }
+    }
+}
+
+// Work done on finished recordings, in the background. The original download is kept unless asked
+// otherwise, since it's the "purest" copy.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PostProcessConfig {
+    pub enabled: bool,
+    // Checks each file with ffprobe before anything else touches it.
+    pub probe: bool,
+    // Container to remux into ("mkv" or "mp4"), without re-encoding. Left alone if unset.
+    pub remux: Option<String>,
+    pub thumbnail: bool,
+    pub metadata: bool,
+    // Only applies to a successful remux, after its duration has been checked against the original.
+    pub keep_original: bool,
+    // Holds the queue while anything is recording, so post-processing doesn't compete for disk.
+    pub idle_only: bool,
+    // Passed to nice for the ffmpeg calls. 0 runs them as is.
+    pub niceness: i32,
+    pub profiles: HashMap<String, TranscodeProfile>,
+    // Channel id to profile name. Channels not listed aren't transcoded.
+    pub channels: HashMap<String, String>,
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> Self {
+        PostProcessConfig {
+            enabled: false,
+            probe: true,
+            remux: Some(String::from("mkv")),
+            thumbnail: true,
+            metadata: true,
+            keep_original: true,
+            idle_only: true,
+            niceness: 10,
+            profiles: HashMap::new(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+// ffmpeg output arguments for a transcode, e.g. ["-c:v", "libx265", "-crf", "26", "-c:a", "copy"].
+#[derive(Clone, Debug, Deserialize)]
+pub struct TranscodeProfile {
+    pub args: Vec<String>,
+    pub extension: String,
+}
+
+impl Config {
+    // The credentials to try for a channel's members-only streams, in order. Channels without a
+    // membership get the default cookies, which is what the "perks." fallback has always used,
+    // followed by every profile in case one of them has access.
+    pub fn credentials_for(&self, channel_id: &str) -> Vec<Credentials> {
+        let default = Credentials::new("default", &Some(self.cookies.file.clone()), &self.cookies.browser,
+            &None, &self.cookies);
+        let profile = |name: &String| match self.credentials.get(name) {
+            Some(profile) => Some(Credentials::new(name, &profile.file, &profile.browser, &profile.proxy,
+                &self.cookies)),
+            None => {
+                warn!("Unknown credential profile: {}", name);
+                None
+            }
+        };
+        let Some(channel) = self.membership.channels.get(channel_id) else {
+            let mut names: Vec<&String> = self.credentials.keys().collect();
+            names.sort();
+            return std::iter::once(default).chain(names.into_iter().filter_map(profile)).collect();
+        };
+        let mut list = Vec::new();
+        if channel.file.is_some() || channel.browser.is_some() {
+            list.push(Credentials::new(channel_id, &channel.file, &channel.browser, &None, &self.cookies));
+        }
+        list.extend(channel.profiles.iter().filter_map(profile));
+        if list.is_empty() {
+            list.push(default);
+        }
+        list
+    }
+
+    pub fn load(path: &str) -> Result<Config, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            info!("No config file found at {}, using defaults.", path);
+            return Ok(Config::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
@@ -0,0 +1,358 @@
+use std::error::Error;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info, warn};
+
+use crate::api_handler::DexClient;
+use crate::availability;
+use crate::catalog::{Catalog, CATALOG_PATH};
+use crate::config::{Config, ControlConfig, CONFIG_PATH};
+use crate::manifest::Manifest;
+use crate::metrics;
+use crate::queue;
+use crate::registry::Registry;
+use crate::stream::{recording_files, DOWNLOAD_DIR};
+
+// Small status page on top of the API below, so there's something to look at besides the log.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+// State shared between the discovery loop, the stream threads and the control server.
+pub struct Control {
+    pub registry: Registry,
+    pub config: RwLock<Config>,
+    // Discovery is skipped while paused; recordings already going are left alone.
+    pub paused: AtomicBool,
+    // Set by the control server, picked up by the discovery loop at the start of its next pass.
+    pub reload: AtomicBool,
+    // Finished recordings to post-process, by id. Set once the queue has been started.
+    pub postprocess: OnceLock<Sender<String>>,
+    // For one-off lookups outside of discovery, such as a finished stream's length.
+    pub dex: Option<DexClient>,
+}
+
+impl Control {
+    pub fn new(config: Config, dex: Option<DexClient>) -> Self {
+        Control {
+            registry: Registry::default(),
+            config: RwLock::new(config),
+            paused: AtomicBool::new(false),
+            reload: AtomicBool::new(false),
+            postprocess: OnceLock::new(),
+            dex,
+        }
+    }
+}
+
+// Starts the control server in its own thread. It's only a handful of cheap endpoints, so requests
+// are handled one at a time.
+pub fn start(control: Arc<Control>) -> Result<(), Box<dyn Error>> {
+    let config = control.config.read().unwrap().control.clone();
+    if !config.enabled {
+        info!("Control server disabled.");
+        return Ok(());
+    }
+
+    let server = match &config.unix_socket {
+        #[cfg(unix)]
+        Some(path) => {
+            // A stale socket from a previous run would otherwise make the bind fail.
+            let _ = std::fs::remove_file(path);
+            info!("Control server listening on {}", path);
+            Server::http_unix(Path::new(path)).map_err(|err| err as Box<dyn Error>)?
+        }
+        _ => {
+            // Anything else on the network could queue and cancel recordings, so that needs the token.
+            let local = config.address.to_socket_addrs()?.all(|addr| addr.ip().is_loopback());
+            if !local && config.token.is_none() {
+                return Err(format!("Control address {} isn't localhost, set a control token to use it.",
+                    config.address).into());
+            }
+            info!("Control server listening on {}", config.address);
+            Server::http(&config.address).map_err(|err| err as Box<dyn Error>)?
+        }
+    };
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handle(&control, &config, request);
+        }
+    });
+    Ok(())
+}
+
+fn handle(control: &Control, config: &ControlConfig, mut request: Request) {
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<String> = path.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().to_string())
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let mut body = String::new();
+    if let Err(err) = request.as_reader().read_to_string(&mut body) {
+        warn!("Failed to read control request body: {:?}", err);
+    }
+
+    // The dashboard and the Prometheus metrics are the only things that aren't JSON.
+    let response = if let Err((status, err)) = allowed(config, &request, &segments) {
+        warn!("Refused control request {} {}: {}", request.method(), url, err);
+        Response::from_string(json!({"error": err}).to_string())
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+    } else if *request.method() == Method::Get && segments.is_empty() {
+        Response::from_string(DASHBOARD)
+            .with_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap())
+    } else if *request.method() == Method::Get && segments == ["metrics"] {
+        Response::from_string(metrics::render(control))
+            .with_header(Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap())
+    } else {
+        let (status, value) = route(control, request.method(), &segments, &body);
+        Response::from_string(value.to_string())
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+    };
+    if let Err(err) = request.respond(response) {
+        warn!("Failed to respond to control request {}: {:?}", url, err);
+    }
+}
+
+// Browsers send requests to localhost on behalf of any page they have open, so only requests that
+// couldn't have come from one are answered: ones with the token, or without a token set, ones addressed
+// to localhost rather than a rebound DNS name. Anything that changes something also needs a JSON body,
+// which another site can't send without the browser asking first.
+fn allowed(config: &ControlConfig, request: &Request, segments: &[&str]) -> Result<(), (u16, &'static str)> {
+    let header = |name: &'static str| request.headers().iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str());
+    // The dashboard page has nothing in it by itself, and picks the token up from its url.
+    let dashboard = *request.method() == Method::Get && segments.is_empty();
+    match &config.token {
+        Some(token) => {
+            if !dashboard && header("Authorization").and_then(|v| v.strip_prefix("Bearer ")) != Some(token) {
+                return Err((401, "Missing or wrong control token."));
+            }
+        }
+        // Whoever can reach the socket can use it anyway.
+        None if cfg!(unix) && config.unix_socket.is_some() => {}
+        None => {
+            if !header("Host").is_some_and(local_host) {
+                return Err((403, "Only requests addressed to localhost are allowed."));
+            }
+        }
+    }
+    if *request.method() != Method::Get && !header("Content-Type").is_some_and(|v| v.starts_with("application/json")) {
+        return Err((415, "Expected Content-Type: application/json."));
+    }
+    Ok(())
+}
+
+// Whether a Host header names this machine.
+fn local_host(host: &str) -> bool {
+    let name = host.rsplit_once(':')
+        .filter(|(_, port)| port.bytes().all(|b| b.is_ascii_digit()))
+        .map_or(host, |(name, _)| name)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn route(control: &Control, method: &Method, segments: &[&str], body: &str) -> (u16, Value) {
+    match (method, segments) {
+        (Method::Get, ["recordings"]) => {
+            (200, to_json(&control.registry.list()))
+        }
+        (Method::Post, ["recordings"]) => {
+            // Goes through the manual queue, so it gets picked up by the discovery loop like
+            // anything else would be.
+            let target = serde_json::from_str::<Value>(body).ok()
+                .and_then(|v| v["target"].as_str().map(str::to_string));
+            match target {
+                Some(target) => match queue::enqueue(&target) {
+                    Ok(_) => {
+                        info!("Control server queued {}", target);
+                        (202, json!({"queued": target}))
+                    }
+                    Err(err) => {
+                        error!("Control server failed to queue {}: {:?}", target, err);
+                        (500, json!({"error": err.to_string()}))
+                    }
+                },
+                None => (400, json!({"error": "Expected a JSON body with a \"target\" field."})),
+            }
+        }
+        (Method::Get, ["recordings", id]) => {
+            match control.registry.get(id) {
+                Some(recording) => (200, to_json(&recording)),
+                None => not_found(id),
+            }
+        }
+        (Method::Get, ["recordings", id, "progress"]) => {
+            match control.registry.get(id) {
+                Some(recording) => (200, to_json(&recording.progress())),
+                None => not_found(id),
+            }
+        }
+        (Method::Get, ["recordings", id, "files"]) => {
+            // Recordings from before manifests were kept can only be found by name.
+            let files = Manifest::load(id).map(|manifest| manifest.files()).unwrap_or_else(|_| recording_files(id));
+            let files: Vec<Value> = files.iter()
+                .map(|path| json!({
+                    "name": path.file_name().map(|n| n.to_string_lossy()),
+                    "size": fs::metadata(path).map(|m| m.len()).ok(),
+                }))
+                .collect();
+            (200, Value::Array(files))
+        }
+        (Method::Get, ["recordings", id, "manifest"]) => {
+            match Manifest::load(id) {
+                Ok(manifest) => (200, to_json(&manifest)),
+                Err(_) => not_found(id),
+            }
+        }
+        (Method::Delete, ["recordings", id]) | (Method::Post, ["recordings", id, "cancel"]) => {
+            if control.registry.cancel(id) {
+                info!("Control server cancelled {}", id);
+                (202, json!({"cancelled": id}))
+            } else {
+                (404, json!({"error": format!("No unfinished recording with id {}.", id)}))
+            }
+        }
+        (Method::Get, ["status"]) => {
+            // Downloads may not exist yet on a fresh install, but the disk is the same either way.
+            let path = if Path::new(DOWNLOAD_DIR).exists() { DOWNLOAD_DIR } else { "." };
+            let disk = match fs2::statvfs(path) {
+                Ok(stats) => json!({
+                    "path": path,
+                    "total": stats.total_space(),
+                    "available": stats.available_space(),
+                }),
+                Err(err) => json!({"error": err.to_string()}),
+            };
+            (200, json!({
+                "paused": control.paused.load(Ordering::Relaxed),
+                "disk": disk,
+            }))
+        }
+        (Method::Get, ["availability"]) => {
+            match Catalog::open(CATALOG_PATH).and_then(|catalog| Ok(catalog.with_status(&availability::LOST)?)) {
+                Ok(lost) => (200, Value::Array(lost.iter().map(|(entry, status, since)| json!({
+                    "id": entry.id,
+                    "channel": entry.channel,
+                    "title": entry.title,
+                    "path": entry.path,
+                    "status": status,
+                    "since": since,
+                })).collect())),
+                Err(err) => (500, json!({"error": err.to_string()})),
+            }
+        }
+        (Method::Get, ["discovery"]) => {
+            (200, json!({"paused": control.paused.load(Ordering::Relaxed)}))
+        }
+        (Method::Post, ["discovery", action @ ("pause" | "resume")]) => {
+            let paused = *action == "pause";
+            control.paused.store(paused, Ordering::Relaxed);
+            info!("Control server set discovery paused: {}", paused);
+            (200, json!({"paused": paused}))
+        }
+        (Method::Post, ["config", "reload"]) => {
+            // The config file is reloaded here so a bad edit is reported back; the lists are left to
+            // the discovery loop, since that's what owns them.
+            match Config::load(CONFIG_PATH) {
+                Ok(config) => {
+                    *control.config.write().unwrap() = config;
+                    control.reload.store(true, Ordering::Relaxed);
+                    info!("Control server reloaded config.");
+                    (200, json!({"reloaded": true}))
+                }
+                Err(err) => {
+                    error!("Failed to reload config: {:?}", err);
+                    (400, json!({"error": err.to_string()}))
+                }
+            }
+        }
+        _ => (404, json!({"error": "Unknown endpoint."})),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or_else(|err| json!({"error": err.to_string()}))
+}
+
+fn not_found(id: &str) -> (u16, Value) {
+    (404, json!({"error": format!("No recording with id {}.", id)}))
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_http::TestRequest;
+
+    use super::*;
+
+    fn request(method: Method, path: &str, headers: &[(&str, &str)]) -> (Request, Vec<String>) {
+        let mut request = TestRequest::new().with_method(method).with_path(path);
+        for (name, value) in headers {
+            request = request.with_header(Header::from_bytes(*name, *value).unwrap());
+        }
+        let segments = path.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        (request.into(), segments)
+    }
+
+    fn check(config: &ControlConfig, method: Method, path: &str, headers: &[(&str, &str)]) -> Result<(), u16> {
+        let (request, segments) = request(method, path, headers);
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        allowed(config, &request, &segments).map_err(|(status, _)| status)
+    }
+
+    #[test]
+    fn local_hosts() {
+        for host in ["localhost", "LOCALHOST:8787", "127.0.0.1", "127.0.0.1:8787", "[::1]", "[::1]:8787"] {
+            assert!(local_host(host), "{}", host);
+        }
+        for host in ["", "example.com", "localhost.example.com", "evil.com:8787", "192.168.1.2:8787", "[::2]:8787"] {
+            assert!(!local_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn without_a_token_only_localhost_is_answered() {
+        let config = ControlConfig::default();
+        assert_eq!(check(&config, Method::Get, "/recordings", &[("Host", "127.0.0.1:8787")]), Ok(()));
+        assert_eq!(check(&config, Method::Get, "/recordings", &[("Host", "rebound.example.com:8787")]), Err(403));
+        assert_eq!(check(&config, Method::Get, "/recordings", &[]), Err(403));
+    }
+
+    #[test]
+    fn changes_need_a_json_body() {
+        let config = ControlConfig::default();
+        let host = ("Host", "localhost:8787");
+        assert_eq!(check(&config, Method::Post, "/discovery/pause", &[host]), Err(415));
+        assert_eq!(check(&config, Method::Post, "/discovery/pause", &[host, ("Content-Type", "text/plain")]),
+            Err(415));
+        assert_eq!(check(&config, Method::Delete, "/recordings/x", &[host, ("Content-Type", "application/json")]),
+            Ok(()));
+    }
+
+    #[test]
+    fn with_a_token_it_is_needed_for_everything_but_the_dashboard() {
+        let config = ControlConfig { token: Some(String::from("secret")), ..Default::default() };
+        // Any host will do once there's a token.
+        let host = ("Host", "recorder.example.com");
+        assert_eq!(check(&config, Method::Get, "/", &[host]), Ok(()));
+        assert_eq!(check(&config, Method::Get, "/recordings", &[host]), Err(401));
+        assert_eq!(check(&config, Method::Get, "/recordings", &[host, ("Authorization", "Bearer wrong")]), Err(401));
+        assert_eq!(check(&config, Method::Get, "/recordings", &[host, ("Authorization", "secret")]), Err(401));
+        assert_eq!(check(&config, Method::Get, "/recordings", &[host, ("Authorization", "Bearer secret")]), Ok(()));
+    }
+}
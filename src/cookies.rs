@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use crate::config::{Config, CookieConfig, NotifyConfig};
+use crate::control::Control;
+use crate::notify::{self, Event, EventKind};
+
+// The cookies YouTube needs to see a signed in account. Any one of them expiring signs it out.
+const AUTH_COOKIES: [&str; 6] = ["SID", "HSID", "SSID", "APISID", "SAPISID", "LOGIN_INFO"];
+
+// Each cookie source that has been alerted about, and its modified time then, so a bad file only
+// alerts once rather than on every check. Replacing the file changes its modified time, which re-arms
+// the alert.
+static ALERTED: Mutex<Vec<(String, Option<SystemTime>)>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Debug)]
+pub struct Cookie {
+    pub domain: String,
+    pub name: String,
+    // None for session cookies.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+// What the configured cookies look like, as far as can be told without asking YouTube.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CookieStatus {
+    // Nothing configured, or the file isn't there.
+    Missing,
+    Invalid(String),
+    // Readable, but not signed in to YouTube.
+    NoAuth,
+    Expired(DateTime<Utc>),
+    Expiring(DateTime<Utc>),
+    // Signed in. The time is the earliest auth cookie expiry, if any of them expire.
+    Valid(Option<DateTime<Utc>>),
+}
+
+impl CookieStatus {
+    pub fn usable(&self) -> bool {
+        matches!(self, CookieStatus::Valid(_) | CookieStatus::Expiring(_))
+    }
+}
+
+impl Display for CookieStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CookieStatus::Missing => write!(f, "no cookies found"),
+            CookieStatus::Invalid(err) => write!(f, "invalid cookie file: {}", err),
+            CookieStatus::NoAuth => write!(f, "no YouTube login cookies"),
+            CookieStatus::Expired(at) => write!(f, "expired on {}", at.format("%Y-%m-%d %H:%M")),
+            CookieStatus::Expiring(at) => write!(f, "expiring on {}", at.format("%Y-%m-%d %H:%M")),
+            CookieStatus::Valid(Some(at)) => write!(f, "valid until {}", at.format("%Y-%m-%d %H:%M")),
+            CookieStatus::Valid(None) => write!(f, "valid (session cookies)"),
+        }
+    }
+}
+
+// Parses a Netscape cookie file, the format yt-dlp reads and writes. Erroring on the first bad line
+// is deliberate: yt-dlp is just as strict, and only says so once a membership stream is found.
+pub fn parse(text: &str) -> Result<Vec<Cookie>, String> {
+    let mut cookies = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        // curl marks HttpOnly cookies by prefixing the domain, which would otherwise be a comment.
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(format!("line {}: expected 7 tab-separated fields, found {}", number + 1, fields.len()));
+        }
+        for (field, value) in [("include subdomains", fields[1]), ("secure", fields[3])] {
+            if !matches!(value, "TRUE" | "FALSE") {
+                return Err(format!("line {}: {} should be TRUE or FALSE, not {:?}", number + 1, field, value));
+            }
+        }
+        let expires = fields[4].parse::<i64>()
+            .map_err(|_| format!("line {}: expiry should be a unix timestamp, not {:?}", number + 1, fields[4]))?;
+        cookies.push(Cookie {
+            domain: fields[0].to_string(),
+            name: fields[5].to_string(),
+            expires: expiry(expires),
+        });
+    }
+    Ok(cookies)
+}
+
+fn expiry(timestamp: i64) -> Option<DateTime<Utc>> {
+    match timestamp {
+        0 => None,
+        // Newer Firefox versions store milliseconds.
+        t if t > 100_000_000_000 => DateTime::from_timestamp_millis(t),
+        t => DateTime::from_timestamp(t, 0),
+    }
+}
+
+// Reads the cookies straight out of a Firefox profile. Opened immutable, since Firefox keeps the
+// database locked while it's running.
+fn firefox_cookies(profile: &Path) -> Result<Vec<Cookie>, Box<dyn Error>> {
+    let path = profile.join("cookies.sqlite");
+    if !path.exists() {
+        return Err(format!("{} not found", path.display()).into());
+    }
+    let connection = Connection::open_with_flags(format!("file:{}?immutable=1", path.display()),
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI)?;
+    let mut statement = connection.prepare("SELECT host, name, expiry FROM moz_cookies")?;
+    let cookies = statement.query_map([], |row| Ok(Cookie {
+        domain: row.get(0)?,
+        name: row.get(1)?,
+        expires: expiry(row.get(2)?),
+    }))?.collect::<Result<Vec<_>, _>>()?;
+    Ok(cookies)
+}
+
+// Splits "firefox:/path/to/profile" into the browser and profile, the same way yt-dlp's
+// --cookies-from-browser does.
+fn browser_spec(browser: &str) -> (&str, Option<&str>) {
+    match browser.split_once(':') {
+        Some((browser, profile)) if !profile.is_empty() => (browser, Some(profile)),
+        _ => (browser.trim_end_matches(':'), None),
+    }
+}
+
+// The yt-dlp option that loads the configured cookies, if any are configured.
+pub fn option(config: &CookieConfig) -> Option<(&'static str, Value)> {
+    match &config.browser {
+        Some(browser) => {
+            let (browser, profile) = browser_spec(browser);
+            Some(("cookiesfrombrowser", json!([browser, profile])))
+        }
+        None if Path::new(&config.file).exists() => Some(("cookiefile", json!(config.file))),
+        None => None,
+    }
+}
+
+// Checks the configured cookies for a YouTube login and when it runs out.
+pub fn check(config: &CookieConfig) -> CookieStatus {
+    let cookies = match &config.browser {
+        Some(browser) => match browser_spec(browser) {
+            ("firefox", Some(profile)) => match firefox_cookies(Path::new(profile)) {
+                Ok(cookies) => cookies,
+                Err(err) => return CookieStatus::Invalid(err.to_string()),
+            },
+            // Other browsers encrypt their cookies, and a bare "firefox" needs yt-dlp's profile
+            // search, so those are left to yt-dlp and only auth failures will tell.
+            _ => return CookieStatus::Valid(None),
+        },
+        None => match fs::read_to_string(&config.file) {
+            Ok(text) => match parse(&text) {
+                Ok(cookies) => cookies,
+                Err(err) => return CookieStatus::Invalid(err),
+            },
+            Err(_) => return CookieStatus::Missing,
+        },
+    };
+    let auth: Vec<&Cookie> = cookies.iter()
+        .filter(|c| c.domain.trim_start_matches('.').ends_with("youtube.com") && AUTH_COOKIES.contains(&c.name.as_str()))
+        .collect();
+    if auth.is_empty() {
+        return CookieStatus::NoAuth;
+    }
+    match auth.iter().filter_map(|c| c.expires).min() {
+        Some(at) if at <= Utc::now() => CookieStatus::Expired(at),
+        Some(at) if at <= Utc::now() + chrono::Duration::days(config.warn_days as i64) => CookieStatus::Expiring(at),
+        at => CookieStatus::Valid(at),
+    }
+}
+
+// When the cookie source last changed, to notice a replaced file or a browser that has refreshed
+// its cookies.
+pub fn modified(config: &CookieConfig) -> Option<SystemTime> {
+    let path = match &config.browser {
+        Some(browser) => match browser_spec(browser) {
+            (_, Some(profile)) => Path::new(profile).join("cookies.sqlite"),
+            _ => return None,
+        },
+        None => Path::new(&config.file).to_path_buf(),
+    };
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// The browser or file the cookies are read from, for logging.
+pub fn source(config: &CookieConfig) -> &str {
+    config.browser.as_deref().unwrap_or(&config.file)
+}
+
+// Sends a cookie_expiry event, unless one has already gone out for the current cookies.
+pub fn alert(config: &CookieConfig, notify: &NotifyConfig, summary: &str, detail: &str) {
+    let current = (source(config).to_string(), modified(config));
+    let mut alerted = ALERTED.lock().unwrap();
+    if alerted.contains(&current) {
+        return;
+    }
+    alerted.retain(|(source, _)| *source != current.0);
+    alerted.push(current);
+    notify::send(notify, Event::new(EventKind::CookieExpiry, summary, detail));
+}
+
+// Logs (and alerts on) the state of the cookies. Returns whether they look usable.
+pub fn report(config: &CookieConfig, notify: &NotifyConfig) -> bool {
+    let status = check(config);
+    let source = source(config);
+    match &status {
+        CookieStatus::Missing => info!("No cookies at {}, membership streams can't be recorded.", source),
+        CookieStatus::Valid(_) => info!("Cookies from {}: {}", source, status),
+        CookieStatus::Expiring(_) => {
+            warn!("Cookies from {}: {}", source, status);
+            alert(config, notify, "Cookies expiring soon", &format!("{}: {}", source, status));
+        }
+        _ => {
+            error!("Cookies from {}: {}", source, status);
+            alert(config, notify, "Cookies need replacing", &format!("{}: {}", source, status));
+        }
+    }
+    status.usable()
+}
+
+// Checks the cookies at startup, then keeps an eye on them. yt-dlp only reads cookies when a YoutubeDL
+// is made, so recordings make a new one before their next attempt once the cookies change; this just
+// says so, and catches cookies running out between membership streams.
+pub fn start(control: Arc<Control>) {
+    let mut last = HashMap::new();
+    thread::spawn(move || loop {
+        let config = control.config.read().unwrap().clone();
+        for cookies in sources(&config) {
+            let current = modified(&cookies);
+            match last.insert(source(&cookies).to_string(), current) {
+                Some(previous) if previous != current => {
+                    info!("Cookies from {} changed, recordings will reload them on their next attempt.",
+                        source(&cookies));
+                }
+                _ => {}
+            }
+            report(&cookies, &config.notify);
+        }
+        thread::sleep(Duration::from_secs(config.cookies.check_minutes * 60));
+    });
+}
+
+// The default cookies, each credential profile's, and each membership's own, without repeats. An
+// unknown channel gets the default and every profile.
+pub fn sources(config: &Config) -> Vec<CookieConfig> {
+    let mut sources: Vec<CookieConfig> = Vec::new();
+    let channels = config.membership.channels.keys().map(String::as_str);
+    for credentials in std::iter::once("").chain(channels).flat_map(|channel| config.credentials_for(channel)) {
+        if !sources.iter().any(|s| source(s) == source(&credentials.cookies)) {
+            sources.push(credentials.cookies);
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(domain: &str, name: &str, expires: i64) -> String {
+        format!("{}\tTRUE\t/\tTRUE\t{}\t{}\tvalue\n", domain, expires, name)
+    }
+
+    // Checks a cookie file with the given contents. Each gets a name of its own, since tests run in
+    // parallel.
+    fn check_file(test: &str, text: &str) -> CookieStatus {
+        let path = std::env::temp_dir().join(format!("akashic-cookies-{}-{}.txt", std::process::id(), test));
+        fs::write(&path, text).unwrap();
+        let status = check(&CookieConfig { file: path.to_string_lossy().to_string(), ..Default::default() });
+        let _ = fs::remove_file(path);
+        status
+    }
+
+    fn signed_in(expires: i64) -> String {
+        AUTH_COOKIES.iter().map(|name| line(".youtube.com", name, expires)).collect()
+    }
+
+    #[test]
+    fn parses_netscape_files() {
+        // With a curl HttpOnly prefix and Windows line endings.
+        let text = format!("# Netscape HTTP Cookie File\n\n{}#HttpOnly_{}",
+            line(".youtube.com", "SID", 1700000000), line(".youtube.com", "HSID", 0).replace('\n', "\r\n"));
+        let cookies = parse(&text).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].name, "SID");
+        assert_eq!(cookies[0].expires, DateTime::from_timestamp(1700000000, 0));
+        // A session cookie.
+        assert_eq!(cookies[1].name, "HSID");
+        assert_eq!(cookies[1].expires, None);
+    }
+
+    #[test]
+    fn reads_millisecond_expiry() {
+        let cookies = parse(&line(".youtube.com", "SID", 1700000000123)).unwrap();
+        assert_eq!(cookies[0].expires, DateTime::from_timestamp_millis(1700000000123));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let good = line(".youtube.com", "SID", 0);
+        let err = parse(&format!("{}.youtube.com\tTRUE\t/\tTRUE\t0\tHSID\n", good)).unwrap_err();
+        assert!(err.starts_with("line 2: expected 7"), "{}", err);
+        let err = parse(&good.replacen("TRUE", "yes", 1)).unwrap_err();
+        assert!(err.contains("include subdomains"), "{}", err);
+        let err = parse(&good.replace("\t0\t", "\tnever\t")).unwrap_err();
+        assert!(err.contains("unix timestamp"), "{}", err);
+        // Space separated, as some exporters get wrong.
+        assert!(parse(&good.replace('\t', " ")).is_err());
+    }
+
+    #[test]
+    fn checks_login_and_expiry() {
+        let now = Utc::now().timestamp();
+        let day = 24 * 60 * 60;
+        assert_eq!(check_file("valid", &signed_in(now + 30 * day)),
+            CookieStatus::Valid(DateTime::from_timestamp(now + 30 * day, 0)));
+        assert_eq!(check_file("session", &signed_in(0)), CookieStatus::Valid(None));
+        assert_eq!(check_file("expiring", &signed_in(now + day)),
+            CookieStatus::Expiring(DateTime::from_timestamp(now + day, 0).unwrap()));
+        assert_eq!(check_file("expired", &signed_in(now - day)),
+            CookieStatus::Expired(DateTime::from_timestamp(now - day, 0).unwrap()));
+
+        // The earliest auth cookie is the one that counts.
+        let text = format!("{}{}", signed_in(now + 30 * day), line("youtube.com", "LOGIN_INFO", now - day));
+        assert!(matches!(check_file("earliest", &text), CookieStatus::Expired(_)));
+    }
+
+    #[test]
+    fn checks_for_a_youtube_login() {
+        let text = format!("{}{}", line(".google.com", "SID", 0), line(".youtube.com", "PREF", 0));
+        assert_eq!(check_file("no-auth", &text), CookieStatus::NoAuth);
+        assert!(matches!(check_file("invalid", "not a cookie file"), CookieStatus::Invalid(_)));
+        let missing = CookieConfig { file: String::from("/nonexistent/cookies.txt"), ..Default::default() };
+        assert_eq!(check(&missing), CookieStatus::Missing);
+    }
+
+    #[test]
+    fn browser_specs() {
+        assert_eq!(browser_spec("firefox"), ("firefox", None));
+        assert_eq!(browser_spec("firefox:"), ("firefox", None));
+        assert_eq!(browser_spec("firefox:/home/pi/profile"), ("firefox", Some("/home/pi/profile")));
+    }
+}
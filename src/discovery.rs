@@ -0,0 +1,506 @@
+use std::cmp;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tracing::{debug, error, info, warn};
+
+use crate::api_handler::{self, DexClient, LiveResponse};
+
+// Where a candidate stream is actually hosted. HoloDex is mostly YouTube, but the placeholders can
+// point to pretty much anything.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Platform {
+    YouTube,
+    Twitch,
+    Other,
+}
+
+impl Platform {
+    pub fn from_url(url: &str) -> Platform {
+        if url.contains("youtu") {
+            Platform::YouTube
+        } else if url.contains("twitch.tv") {
+            Platform::Twitch
+        } else {
+            Platform::Other
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LiveStatus {
+    Upcoming,
+    Live,
+    Past,
+    Unknown,
+}
+
+impl LiveStatus {
+    pub fn parse(status: &str) -> LiveStatus {
+        match status {
+            "upcoming" => LiveStatus::Upcoming,
+            "live" => LiveStatus::Live,
+            "past" => LiveStatus::Past,
+            _ => LiveStatus::Unknown,
+        }
+    }
+}
+
+// A stream found by a discovery source, normalized so the matcher doesn't need to know anything
+// about where it came from.
+// The id is whatever the source uses to uniquely identify the stream, and is what the found set is
+// keyed on. The url is the actual thing handed to yt-dlp, and is None when there's nothing to
+// record yet (e.g. a scheduled placeholder without a waiting room).
+// Requested candidates were explicitly asked for (the manual queue) and skip the matcher.
+// Members-only is as far as the source can tell; a stream that isn't flagged can still turn out to be
+// one once yt-dlp gets to it.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub platform: Platform,
+    pub id: String,
+    pub url: Option<String>,
+    pub channel_id: String,
+    pub channel_name: String,
+    pub title: String,
+    pub scheduled_start: Option<DateTime<Utc>>,
+    pub status: LiveStatus,
+    pub requested: bool,
+    pub members_only: bool,
+}
+
+impl Candidate {
+    // Builds a candidate from a bare url or video id, for targets that were asked for directly rather
+    // than found by a source.
+    pub fn from_target(target: &str) -> Candidate {
+        let target = target.trim();
+        let (platform, id, url) = match youtube_id(target) {
+            Some(id) => {
+                let url = format!("https://www.youtube.com/watch?v={}", id);
+                (Platform::YouTube, id, url)
+            }
+            None => (Platform::from_url(target), target.to_string(), target.to_string()),
+        };
+
+        Candidate {
+            platform,
+            id,
+            url: Some(url),
+            channel_id: String::new(),
+            channel_name: String::from("manual"),
+            title: String::new(),
+            scheduled_start: None,
+            status: LiveStatus::Unknown,
+            requested: true,
+            members_only: false,
+        }
+    }
+
+    // The value to pass to the StreamManager, or None when there's nothing to record yet. YouTube
+    // videos are passed as the bare id, since that's what the rest of the project expects. The id is
+    // taken from the url rather than the candidate, since a placeholder's id is HoloDex's own.
+    pub fn target(&self) -> Option<String> {
+        let url = self.url.as_ref().filter(|url| !url.is_empty())?;
+        match self.platform {
+            Platform::YouTube => Some(youtube_id(url).unwrap_or_else(|| url.clone())),
+            _ => Some(url.clone()),
+        }
+    }
+}
+
+// Pulls the 11 character video id out of the usual YouTube url formats, or accepts a bare id.
+pub fn youtube_id(target: &str) -> Option<String> {
+    let target = target.trim();
+    let is_id = |s: &str| s.len() == 11
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if is_id(target) {
+        return Some(target.to_string());
+    }
+    if !target.contains("youtu") {
+        return None;
+    }
+    // watch?v=, youtu.be/, /live/ and /shorts/ all put the id right after a marker.
+    ["v=", "youtu.be/", "/live/", "/shorts/"].iter()
+        .filter_map(|marker| target.split(marker).nth(1))
+        .map(|rest| rest.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect::<String>())
+        .find(|id| is_id(id))
+}
+
+// Tags streamers put in the titles of members-only streams. Checked the same way as keywords, against
+// the lowercase title with whitespace removed.
+const MEMBERS_TAGS: [&str; 7] = ["membersonly", "members-only", "member'sonly", "memberonly", "メン限",
+    "メンバー限定", "メンバーシップ限定"];
+
+pub fn members_tag(title: &str) -> bool {
+    let mut title = title.to_lowercase();
+    title.retain(|c| !c.is_whitespace());
+    MEMBERS_TAGS.iter().any(|tag| title.contains(tag))
+}
+
+// Anything that can produce candidate streams. The core loop polls every source it's given each
+// pass, so a new site (or a manual queue, or an RSS feed) only needs to implement this.
+pub trait DiscoverySource: Send {
+    // Used for logging, mostly.
+    fn name(&self) -> &str;
+
+    fn poll(&mut self) -> Result<Vec<Candidate>, Box<dyn Error>>;
+
+    // Whether the source wants polling this pass. A source backing off after failures sits passes out.
+    fn ready(&self) -> bool {
+        true
+    }
+
+    // How many requests a poll makes, counted against the discovery budget. Local sources make none,
+    // and are polled every tick.
+    fn requests(&self) -> u32 {
+        0
+    }
+
+    // Polled on the priority schedule rather than with the full polls.
+    fn priority(&self) -> bool {
+        false
+    }
+}
+
+// Exponential backoff with jitter, so a source that's down gets tried less and less often, and
+// recorders started together don't all come back at once.
+#[derive(Default)]
+pub struct Backoff {
+    failures: u32,
+    until: Option<Instant>,
+}
+
+impl Backoff {
+    const BASE: Duration = Duration::from_secs(60);
+    const MAX: Duration = Duration::from_secs(30 * 60);
+
+    pub fn ready(&self) -> bool {
+        self.until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    // Backs off again, or for as long as the source asked if that's longer. Returns the wait.
+    pub fn fail(&mut self, asked: Option<Duration>) -> Duration {
+        let delay = cmp::min(Self::BASE * 2u32.saturating_pow(self.failures), Self::MAX);
+        // Somewhere between half and all of the delay.
+        let delay = delay / 2 + delay.mul_f64(fastrand::f64() / 2.0);
+        let delay = cmp::max(delay, asked.unwrap_or_default());
+        self.failures += 1;
+        self.until = Some(Instant::now() + delay);
+        delay
+    }
+
+    pub fn succeed(&mut self) {
+        self.failures = 0;
+        self.until = None;
+    }
+}
+
+// A source being down, from the first failure on. Logs the first failure in full, then only how long
+// it's been down, every so often, instead of a line per attempt.
+pub struct Outage {
+    since: Instant,
+    attempts: u32,
+    reported: Instant,
+    // Whether it's been down long enough to have been reported as an outage rather than a blip.
+    lasting: bool,
+}
+
+impl Outage {
+    const REPORT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    pub fn start(name: &str, err: &dyn Error) -> Outage {
+        error!("{}: Request failed: {}", name, err);
+        Outage { since: Instant::now(), attempts: 1, reported: Instant::now(), lasting: false }
+    }
+
+    // Returns true the first time the outage is reported, for anything worth telling someone about.
+    pub fn failed(&mut self, name: &str, err: &dyn Error) -> bool {
+        self.attempts += 1;
+        debug!("{}: Request failed: {}", name, err);
+        if self.reported.elapsed() < Self::REPORT_INTERVAL {
+            return false;
+        }
+        warn!("{} unreachable for {} minutes ({} failed attempts). Last error: {}", name, self.minutes(),
+            self.attempts, err);
+        self.reported = Instant::now();
+        !std::mem::replace(&mut self.lasting, true)
+    }
+
+    pub fn end(self, name: &str) {
+        info!("{} reachable again after {} minutes ({} failed attempts).", name, self.minutes(), self.attempts);
+    }
+
+    pub fn minutes(&self) -> u64 {
+        self.since.elapsed().as_secs() / 60
+    }
+}
+
+pub struct HoloDexSource {
+    client: DexClient,
+    // Only these channels, through the per-channel endpoint. Empty for all of /live.
+    channels: Vec<String>,
+    // The last list and its ETag, for when HoloDex says nothing has changed.
+    etag: Option<String>,
+    cached: Vec<Candidate>,
+    backoff: Backoff,
+}
+
+impl HoloDexSource {
+    pub fn new(client: DexClient) -> Self {
+        Self::channels(client, Vec::new())
+    }
+
+    pub fn channels(client: DexClient, channels: Vec<String>) -> Self {
+        HoloDexSource {
+            client,
+            channels,
+            etag: None,
+            cached: Vec::new(),
+            backoff: Backoff::default(),
+        }
+    }
+
+    // Converts a single entry from the /live endpoint.
+    // Usually the stream to download is a YouTube stream with a unique id, but other sources
+    // (Twitch) work differently.
+    fn normalize(val: &Value) -> Option<Candidate> {
+        let mut id = val["id"].as_str()?.to_string();
+        let status = LiveStatus::parse(val["status"].as_str().unwrap_or_default());
+        let (platform, url) = if val["type"] == "stream" {
+            (Platform::YouTube, Some(format!("https://www.youtube.com/watch?v={}", id)))
+        } else if val["placeholderType"] == "external-stream" {
+            // Generally Twitch, but may pick up Twitter Spaces or other odd ball sources. Without a
+            // link there's nothing to record yet.
+            let link = val["link"].as_str().filter(|link| !link.is_empty());
+            // A link to a YouTube video is recorded (and kept track of) as that video.
+            if let Some(video) = link.filter(|link| link.contains("youtu")).and_then(youtube_id) {
+                id = video;
+            }
+            (link.map_or(Platform::Other, Platform::from_url), link.map(str::to_string))
+        } else if val["placeholderType"] == "scheduled-yt-stream" {
+            // Stream is expected based on a posted schedule or some other source, but a waiting room
+            // hasn't been found yet. There is a "certainty" value, but I don't see any way to make it
+            // relevant, nor is there really anything to do with a stream that doesn't exist yet.
+            (Platform::YouTube, None)
+        } else {
+            // Included (and at warn level) to ensure nothing is slipping through.
+            warn!("Unrecognized entry from HoloDex: {}", val);
+            return None;
+        };
+
+        Some(Candidate {
+            platform,
+            id,
+            url,
+            channel_id: val["channel"]["id"].as_str().unwrap_or_default().to_string(),
+            channel_name: val["channel"]["name"].as_str().unwrap_or_default().to_string(),
+            title: val["title"].as_str().unwrap_or_default().to_string(),
+            scheduled_start: val["start_scheduled"].as_str()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|d| d.with_timezone(&Utc)),
+            status,
+            requested: false,
+            members_only: val["topic_id"] == "membersonly" || members_tag(val["title"].as_str().unwrap_or_default()),
+        })
+    }
+}
+
+impl DiscoverySource for HoloDexSource {
+    fn name(&self) -> &str {
+        match self.channels.is_empty() {
+            true => "HoloDex",
+            false => "HoloDex priority channels",
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<Candidate>, Box<dyn Error>> {
+        let response = match self.channels.is_empty() {
+            true => self.client.live_check(self.etag.as_deref()),
+            false => self.client.channels_check(&self.channels, self.etag.as_deref()),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                let wait = self.backoff.fail(None);
+                return Err(format!("{} (next try in {}s)", err, wait.as_secs()).into());
+            }
+        };
+        match response {
+            LiveResponse::Changed(Value::Array(list), etag) => {
+                debug!("Response status is success");
+                self.backoff.succeed();
+                self.cached = list.iter().filter_map(Self::normalize).collect();
+                self.etag = etag;
+                Ok(self.cached.clone())
+            }
+            LiveResponse::Changed(val, _) => {
+                self.backoff.fail(None);
+                Err(format!("Unexpected response from HoloDex: {}", val).into())
+            }
+            LiveResponse::NotModified => {
+                debug!("HoloDex live list unchanged.");
+                self.backoff.succeed();
+                Ok(self.cached.clone())
+            }
+            LiveResponse::RateLimited(asked) => {
+                let wait = self.backoff.fail(asked);
+                Err(format!("Rate limited, next try in {}s.", wait.as_secs()).into())
+            }
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.backoff.ready() && api_handler::holodex_wait().is_none()
+    }
+
+    fn requests(&self) -> u32 {
+        1
+    }
+
+    fn priority(&self) -> bool {
+        !self.channels.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn youtube_ids() {
+        assert_eq!(youtube_id("dQw4w9WgXcQ").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(youtube_id(" dQw4w9WgXcQ\n").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(youtube_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(youtube_id("https://youtube.com/watch?feature=share&v=dQw4w9WgXcQ").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(youtube_id("https://youtu.be/dQw4w9WgXcQ?si=abc").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(youtube_id("https://www.youtube.com/live/dQw4w9WgXcQ").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(youtube_id("https://www.youtube.com/shorts/dQw4w9WgXcQ").as_deref(), Some("dQw4w9WgXcQ"));
+    }
+
+    #[test]
+    fn not_youtube_ids() {
+        assert_eq!(youtube_id(""), None);
+        assert_eq!(youtube_id("dQw4w9WgXc"), None);
+        assert_eq!(youtube_id("https://www.twitch.tv/somechannel"), None);
+        // Eleven characters after a marker, but not on YouTube.
+        assert_eq!(youtube_id("https://example.com/watch?v=dQw4w9WgXcQ"), None);
+        assert_eq!(youtube_id("https://www.youtube.com/watch?v=short"), None);
+        assert_eq!(youtube_id("https://www.youtube.com/@channel"), None);
+    }
+
+    fn placeholder(link: Value) -> Value {
+        json!({
+            "id": "holodex-placeholder",
+            "type": "placeholder",
+            "placeholderType": "external-stream",
+            "status": "upcoming",
+            "title": "Collab",
+            "link": link,
+            "channel": {"id": "UC123", "name": "Someone"},
+        })
+    }
+
+    #[test]
+    fn normalizes_streams() {
+        let candidate = HoloDexSource::normalize(&json!({
+            "id": "dQw4w9WgXcQ",
+            "type": "stream",
+            "status": "live",
+            "title": "【Members Only】 Karaoke",
+            "start_scheduled": "2024-01-02T03:04:05.000Z",
+            "channel": {"id": "UC123", "name": "Someone"},
+        })).unwrap();
+        assert_eq!(candidate.platform, Platform::YouTube);
+        assert_eq!(candidate.id, "dQw4w9WgXcQ");
+        assert_eq!(candidate.url.as_deref(), Some("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert_eq!(candidate.target().as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(candidate.channel_id, "UC123");
+        assert_eq!(candidate.status, LiveStatus::Live);
+        assert!(candidate.scheduled_start.is_some());
+        assert!(candidate.members_only);
+    }
+
+    #[test]
+    fn normalizes_external_placeholders() {
+        let twitch = HoloDexSource::normalize(&placeholder(json!("https://www.twitch.tv/someone"))).unwrap();
+        assert_eq!(twitch.platform, Platform::Twitch);
+        assert_eq!(twitch.id, "holodex-placeholder");
+        assert_eq!(twitch.target().as_deref(), Some("https://www.twitch.tv/someone"));
+
+        // Kept track of as the video it links to.
+        let youtube = HoloDexSource::normalize(&placeholder(json!("https://youtu.be/dQw4w9WgXcQ"))).unwrap();
+        assert_eq!(youtube.platform, Platform::YouTube);
+        assert_eq!(youtube.id, "dQw4w9WgXcQ");
+        assert_eq!(youtube.target().as_deref(), Some("dQw4w9WgXcQ"));
+    }
+
+    #[test]
+    fn placeholders_without_links_have_nothing_to_record() {
+        for link in [json!(""), json!(null)] {
+            let candidate = HoloDexSource::normalize(&placeholder(link)).unwrap();
+            assert_eq!(candidate.platform, Platform::Other);
+            assert_eq!(candidate.url, None);
+            assert_eq!(candidate.target(), None);
+        }
+        let scheduled = HoloDexSource::normalize(&json!({"id": "x", "placeholderType": "scheduled-yt-stream"}))
+            .unwrap();
+        assert_eq!(scheduled.target(), None);
+    }
+
+    #[test]
+    fn skips_unrecognized_entries() {
+        assert!(HoloDexSource::normalize(&json!({"id": "x", "type": "clip"})).is_none());
+        assert!(HoloDexSource::normalize(&json!({"type": "stream"})).is_none());
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_a_cap() {
+        let mut backoff = Backoff::default();
+        assert!(backoff.ready());
+        for failures in 0..10u32 {
+            let full = cmp::min(Backoff::BASE * 2u32.pow(failures), Backoff::MAX);
+            let wait = backoff.fail(None);
+            assert!(wait >= full / 2 && wait <= full, "{:?} after {} failures", wait, failures);
+            assert!(!backoff.ready());
+        }
+        backoff.succeed();
+        assert!(backoff.ready());
+        let wait = backoff.fail(None);
+        assert!(wait <= Backoff::BASE);
+    }
+
+    #[test]
+    fn backoff_waits_as_long_as_asked() {
+        let mut backoff = Backoff::default();
+        let asked = Duration::from_secs(3600);
+        assert_eq!(backoff.fail(Some(asked)), asked);
+        // Never shorter than its own delay, though.
+        assert!(backoff.fail(Some(Duration::from_secs(1))) >= Backoff::BASE);
+    }
+
+    #[test]
+    fn outage_is_reported_once_it_lasts() {
+        let err: Box<dyn Error> = "connection refused".into();
+        let mut outage = Outage::start("HoloDex", err.as_ref());
+        // A blip, too soon to be worth telling anyone.
+        assert!(!outage.failed("HoloDex", err.as_ref()));
+        assert_eq!(outage.attempts, 2);
+
+        let ago = Instant::now() - Outage::REPORT_INTERVAL;
+        outage.since = ago;
+        outage.reported = ago;
+        assert!(outage.failed("HoloDex", err.as_ref()));
+        assert_eq!(outage.minutes(), Outage::REPORT_INTERVAL.as_secs() / 60);
+
+        // Logged again every so often, but only reported the first time.
+        assert!(!outage.failed("HoloDex", err.as_ref()));
+        outage.reported = ago;
+        assert!(!outage.failed("HoloDex", err.as_ref()));
+        assert!(outage.lasting);
+    }
+}
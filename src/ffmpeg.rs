@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use tracing::debug;
+
+// Thin wrappers around the ffmpeg/ffprobe binaries. yt-dlp already needs ffmpeg for most live
+// streams, so these are assumed to be on the path.
+
+// Container duration in seconds, as ffprobe reports it.
+pub fn probe_duration(path: &Path) -> Result<f64, Box<dyn Error>> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("ffprobe failed on {}: {}", path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.trim().parse::<f64>()
+        .map_err(|_| format!("ffprobe returned no duration for {}: {}", path.display(), text.trim()).into())
+}
+
+// Joins files end to end without re-encoding, using the concat demuxer. The list file is written
+// next to the output and removed afterwards.
+pub fn concat(files: &[&Path], output: &Path) -> Result<(), Box<dyn Error>> {
+    let list = output.with_extension("concat.txt");
+    let mut text = String::new();
+    for file in files {
+        // The concat demuxer wants single quotes escaped the shell way.
+        let absolute = fs::canonicalize(file)?;
+        text.push_str(&format!("file '{}'\n", absolute.to_string_lossy().replace('\'', "'\\''")));
+    }
+    fs::write(&list, text)?;
+
+    debug!("Concatenating {} files into {}", files.len(), output.display());
+    let result = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-n", "-f", "concat", "-safe", "0", "-i"])
+        .arg(&list)
+        .args(["-map", "0", "-c", "copy"])
+        .arg(output)
+        .output();
+    let _ = fs::remove_file(&list);
+
+    let output_status = result?;
+    if !output_status.status.success() {
+        return Err(format!("ffmpeg concat failed: {}",
+            String::from_utf8_lossy(&output_status.stderr).trim()).into());
+    }
+    Ok(())
+}
+
+// Runs ffmpeg with the given arguments, under nice if a niceness is given. Never overwrites.
+pub fn run(args: &[OsString], niceness: i32) -> Result<(), Box<dyn Error>> {
+    let mut command = if niceness != 0 {
+        let mut command = Command::new("nice");
+        command.args(["-n", &niceness.to_string(), "ffmpeg"]);
+        command
+    } else {
+        Command::new("ffmpeg")
+    };
+    let output = command
+        .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-n"])
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(())
+}
+
+// Reads the whole file through ffmpeg without writing anything, returning any errors it reported.
+// Without a full decode the packets are only copied, which catches container damage but not a
+// broken bitstream.
+pub fn check(path: &Path, full_decode: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-nostdin", "-v", "error", "-i"]).arg(path);
+    if !full_decode {
+        command.args(["-map", "0", "-c", "copy"]);
+    }
+    let output = command.args(["-f", "null", "-"]).output()?;
+    let errors: Vec<String> = String::from_utf8_lossy(&output.stderr).lines()
+        .map(str::to_string)
+        .collect();
+    if !output.status.success() && errors.is_empty() {
+        return Err(format!("ffmpeg exited with {}", output.status).into());
+    }
+    Ok(errors)
+}
@@ -0,0 +1,155 @@
+use std::io::{Read, Write};
+use std::mem;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{debug, error, info, warn};
+
+use crate::config::HooksConfig;
+use crate::manifest::Manifest;
+use crate::stream::Outcome;
+
+// Hooks still running in the background, so a command that exits once it's done can wait for them.
+static RUNNING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Debug)]
+pub enum Hook {
+    Start,
+    Finish,
+    Fail,
+    PostProcessed,
+}
+
+impl Hook {
+    fn name(&self) -> &'static str {
+        match self {
+            Hook::Start => "start",
+            Hook::Finish => "finish",
+            Hook::Fail => "fail",
+            Hook::PostProcessed => "postprocessed",
+        }
+    }
+
+    fn command<'a>(&self, config: &'a HooksConfig) -> &'a [String] {
+        match self {
+            Hook::Start => &config.on_start,
+            Hook::Finish => &config.on_finish,
+            Hook::Fail => &config.on_fail,
+            Hook::PostProcessed => &config.on_postprocessed,
+        }
+    }
+}
+
+// What a hook script gets on stdin. The same values (other than the manifest) are also set as
+// AKASHIC_* environment variables, for scripts that would rather not parse JSON.
+#[derive(Debug, Serialize)]
+pub struct Payload {
+    pub hook: &'static str,
+    pub id: String,
+    pub target: String,
+    pub channel: String,
+    pub channel_id: String,
+    pub title: String,
+    pub rule: String,
+    pub outcome: Option<Outcome>,
+    // The recording itself: the joined file, or each part.
+    pub videos: Vec<String>,
+    // Everything in the download folder for the recording, including info.json, thumbnails, chat
+    // and post-processed copies.
+    pub files: Vec<String>,
+    pub manifest: String,
+    pub time: DateTime<Utc>,
+}
+
+impl Payload {
+    pub fn new(hook: Hook, manifest: &Manifest, outcome: Option<Outcome>) -> Payload {
+        let files: Vec<String> = manifest.files().iter()
+            .map(|f| f.to_string_lossy().to_string())
+            .collect();
+        Payload {
+            hook: hook.name(),
+            id: manifest.id.clone(),
+            target: manifest.target.clone(),
+            channel: manifest.channel.clone(),
+            channel_id: manifest.channel_id.clone(),
+            title: manifest.title.clone(),
+            rule: manifest.rule.clone(),
+            outcome,
+            videos: manifest.video_files().iter().map(|f| f.to_string_lossy().to_string()).collect(),
+            files,
+            manifest: Manifest::path(&manifest.id).to_string_lossy().to_string(),
+            time: Utc::now(),
+        }
+    }
+}
+
+// Runs the configured script for a hook, if any, in the background. Scripts are trusted and can
+// take as long as they like; only their exit status and stderr are logged.
+pub fn run(config: &HooksConfig, hook: Hook, manifest: &Manifest, outcome: Option<Outcome>) {
+    let command = hook.command(config).to_vec();
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    let payload = Payload::new(hook, manifest, outcome);
+    let (program, args) = (program.clone(), args.to_vec());
+    let handle = thread::spawn(move || {
+        let json = match serde_json::to_string(&payload) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("{}: Failed to serialize {} hook payload: {:?}", payload.id, payload.hook, err);
+                return;
+            }
+        };
+        debug!("{}: Running {} hook: {}", payload.id, payload.hook, program);
+        let child = Command::new(&program)
+            .args(&args)
+            .env("AKASHIC_HOOK", payload.hook)
+            .env("AKASHIC_ID", &payload.id)
+            .env("AKASHIC_TARGET", &payload.target)
+            .env("AKASHIC_CHANNEL", &payload.channel)
+            .env("AKASHIC_CHANNEL_ID", &payload.channel_id)
+            .env("AKASHIC_TITLE", &payload.title)
+            .env("AKASHIC_RULE", &payload.rule)
+            .env("AKASHIC_OUTCOME", payload.outcome.as_ref().map(|o| o.to_string()).unwrap_or_default())
+            .env("AKASHIC_VIDEOS", payload.videos.join("\n"))
+            .env("AKASHIC_FILES", payload.files.join("\n"))
+            .env("AKASHIC_MANIFEST", &payload.manifest)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                error!("{}: Failed to start {} hook {}: {:?}", payload.id, payload.hook, program, err);
+                return;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            // A script that doesn't read stdin closes it early, which is fine.
+            let _ = stdin.write_all(json.as_bytes());
+        }
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        match child.wait() {
+            Ok(status) if status.success() => info!("{}: {} hook finished.", payload.id, payload.hook),
+            Ok(status) => warn!("{}: {} hook exited with {}: {}", payload.id, payload.hook, status, stderr.trim()),
+            Err(err) => error!("{}: Failed to wait on {} hook: {:?}", payload.id, payload.hook, err),
+        }
+    });
+    let mut running = RUNNING.lock().unwrap();
+    running.retain(|handle| !handle.is_finished());
+    running.push(handle);
+}
+
+// Waits for hooks started in the background to finish, however long they take, e.g. before exiting.
+pub fn wait() {
+    for handle in mem::take(&mut *RUNNING.lock().unwrap()) {
+        let _ = handle.join();
+    }
+}
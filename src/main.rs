@@ -1,18 +1,52 @@
 use std::{fs, thread, thread::sleep, time};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
 use serde_json::Value;
-use tracing::{debug, error, info, Level, span, subscriber, warn, trace};
-use tracing::{info_span, Span};
+use tracing::{debug, error, info, subscriber, warn};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_panic::panic_hook;
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, Registry};
 use tracing_subscriber::fmt::time::ChronoLocal;
 use crate::api_handler::*;
-use crate::stream::StreamManager;
+use crate::catalog::Catalog;
+use crate::config::{Config, CONFIG_PATH};
+use crate::control::Control;
+use crate::discovery::{Candidate, DiscoverySource, HoloDexSource, LiveStatus, Outage, Platform};
+use crate::manifest::VerifyStatus;
+use crate::matcher::{Matcher, MatchRule};
+use crate::notify::{Event, EventKind};
+use crate::queue::ManualSource;
+use crate::registry::RecordingHandle;
+use crate::schedule::Scheduler;
+use crate::stream::{Outcome, StreamManager};
 
 mod api_handler;
+mod availability;
+mod catalog;
+mod chat;
+mod config;
+mod control;
+mod cookies;
+mod discovery;
+mod ffmpeg;
+mod hooks;
+mod manifest;
+mod matcher;
+mod metrics;
+mod notify;
+mod postprocess;
+mod quota;
+mod queue;
+mod registry;
+mod schedule;
 mod stream;
+mod verify;
+mod watchdog;
 
 // Base file parsing function. Not entirely happy with returning a VecDeque, but it works for now.
 fn read_file(file_name: &str) -> Result<VecDeque<String>, Box<dyn Error>> {
@@ -30,29 +64,14 @@ fn read_file(file_name: &str) -> Result<VecDeque<String>, Box<dyn Error>> {
     }
 }
 
-// Loop to periodically call the HoloDex API to find new streams.
-// Checks against a hashset to determine if a stream is already downloaded, has been checked before,
-// or has changes to the title (that may change the status).
-// TODO: found_list should be split into downloading and noticed hashsets.
-// TODO: Consider making into a struct, to share the various data-structures, thus allowing easier
-//  function changing/breakdowns.
-// TODO: Passing via pipe.
-// TODO: Chat logging.
-fn api_loop() -> Result<(), Box<dyn Error>> {
-    let dex_key = match read_file("res/keys/holodex_Key.txt") {
-        Ok(mut file) => {
-            file.pop_front().unwrap()
-        }
-        Err(err) => {
-            panic!("Error reading key file: {:?}", err);
-        }
-    };
+// Reads the lists the matcher works from. The archive and check lists are required, see the ReadMe.
+fn load_matcher() -> Result<Matcher, Box<dyn Error>> {
     let archive_set: HashSet<String> = match read_file("res/lists/archive_list.txt") {
         Ok(file) => {
             HashSet::from_iter(file)
         }
         Err(err) => {
-            panic!("Error reading archive list: {:?}", err)
+            return Err(format!("Error reading archive list: {:?}", err).into())
         }
     };
     let check_set: HashSet<String> = match read_file("res/lists/check_list.txt") {
@@ -60,7 +79,7 @@ fn api_loop() -> Result<(), Box<dyn Error>> {
             HashSet::from_iter(file)
         }
         Err(err) => {
-            panic!("Error reading check list: {:?}", err)
+            return Err(format!("Error reading check list: {:?}", err).into())
         }
     };
     // Unlike the above, this one being empty isn't a deal-breaker.
@@ -68,115 +87,343 @@ fn api_loop() -> Result<(), Box<dyn Error>> {
         error!("Error reading keyword list: {:?}", e);
         VecDeque::new()
     });
+    Ok(Matcher::new(archive_set, check_set, key_word_set))
+}
+
+// Loop to periodically poll the discovery sources to find new streams.
+// Checks against a hashset to determine if a stream is already downloaded, has been checked before,
+// or has changes to the title (that may change the status).
+// TODO: found_list should be split into downloading and noticed hashsets.
+fn api_loop(mut sources: Vec<Box<dyn DiscoverySource>>, control: Arc<Control>) -> Result<(), Box<dyn Error>> {
+    let mut matcher = match load_matcher() {
+        Ok(matcher) => matcher,
+        Err(err) => panic!("{}", err),
+    };
     let mut found_set: HashSet<String> = HashSet::new();
-    let api_caller = DexClient::new(dex_key);
+    // Only the change into each state is notified, not every pass spent in it.
+    let mut outages: HashMap<String, Outage> = HashMap::new();
+    let mut disk_low = false;
+    let mut scheduler = Scheduler::new(control.config.read().unwrap().discovery.clone());
+    sources.extend(priority_source(&control));
     loop {
-        debug!("Start of loop, checking for API response.");
-        let response: Value = loop {
-            match api_caller.live_check() {
-                Ok(val) => {
-                    if val.status().is_success() {
-                        debug!("Response status is success");
-                        break val.json::<Value>().unwrap();
-                    } else {
-                        error!("Bad response status: {:?}.", val.status());
-                    }
-                }
-                Err(error) => {
-                    error!("Request failed: {:?}.", error)
+        if control.reload.swap(false, Ordering::Relaxed) {
+            // A bad edit shouldn't take down a running recorder, so the old lists are kept.
+            match load_matcher() {
+                Ok(new) => {
+                    info!("Reloaded lists.");
+                    matcher = new;
                 }
+                Err(err) => error!("Failed to reload lists, keeping the old ones: {}", err),
+            }
+            scheduler.configure(control.config.read().unwrap().discovery.clone());
+            sources.retain(|source| !source.priority());
+            sources.extend(priority_source(&control));
+        }
+
+        if control.paused.load(Ordering::Relaxed) {
+            debug!("Discovery paused, skipping sources.");
+            sleep(schedule::TICK);
+            continue;
+        }
+
+        let full = scheduler.full_due();
+        let priority = scheduler.priority_due();
+        // Over budget, the pass is tried again next tick rather than waiting out another interval.
+        let (mut polled_full, mut polled_priority) = (false, false);
+        for source in sources.iter_mut() {
+            let requests = source.requests();
+            let due = match source.priority() {
+                _ if requests == 0 => true,
+                true => priority,
+                false => full,
             };
-            // On failed request, wait two minutes before trying again.
-            // Generally, this is either from calling before the device has connected to the internet
-            // or because HoloDex is down.
-            debug!("Starting response sleep");
-            sleep(time::Duration::from_secs(120));
-        };
-
-        debug!("Starting response loop.");
-        for val in response.as_array().unwrap() {
-            if found_set.contains(val["id"].as_str().unwrap()) {
-                debug!("Re-found a stream");
+            if !due {
                 continue;
             }
-
-            if archive_set.contains(val["channel"]["id"].as_str().unwrap()) {
-                if let Some(id) = target_parse(val) {
-                    found_set.insert(id);
-                }
+            if !source.ready() {
+                debug!("{}: Backing off, skipping this pass.", source.name());
                 continue;
             }
-
-            let mut title = val["title"].to_string().to_lowercase();
-            title.retain(|c| !c.is_whitespace());
-
-            if check_set.contains(&val["channel"]["id"].to_string()) {
-                for word in &key_word_set {
-                    if title.contains(word) {
-                        if let Some(id) = target_parse(val) {
-                            found_set.insert(id);
+            if !scheduler.spend(requests) {
+                continue;
+            }
+            match source.priority() {
+                _ if requests == 0 => {}
+                true => polled_priority = true,
+                false => polled_full = true,
+            }
+            debug!("Polling {} for streams.", source.name());
+            // On a failed request, the source is skipped until the next pass.
+            // Generally, this is either from calling before the device has connected to the internet
+            // or because the source is down.
+            let name = source.name().to_string();
+            let candidates = match source.poll() {
+                Ok(candidates) => candidates,
+                Err(err) => {
+                    match outages.get_mut(&name) {
+                        Some(outage) => {
+                            // A failed poll or two is nothing to be told about; one that lasts is.
+                            if outage.failed(&name, err.as_ref()) {
+                                notify::send(&control.config.read().unwrap().notify,
+                                    Event::new(EventKind::HolodexUnreachable, &format!("{} unreachable", name),
+                                        &format!("{} unreachable for {} minutes. Last error: {}", name,
+                                            outage.minutes(), err)));
+                            }
+                        }
+                        None => {
+                            outages.insert(name.clone(), Outage::start(&name, err.as_ref()));
                         }
-                        continue;
                     }
+                    continue;
                 }
+            };
+            if let Some(outage) = outages.remove(&name) {
+                outage.end(&name);
             }
 
-            if title.contains("unarchived") {
-                if let Some(id) = target_parse(val) {
-                    found_set.insert(id);
+            debug!("Starting response loop.");
+            for candidate in candidates {
+                scheduler.seen(&candidate, matcher.watches(&candidate.channel_id));
+                if found_set.contains(&candidate.id) && !control.registry.retry(&candidate) {
+                    debug!("Re-found a stream");
+                    continue;
                 }
-                continue;
+
+                if let Some(rule) = matcher.check(&candidate) {
+                    metrics::MATCHES.inc(&[("rule", rule.name())]);
+                    if let Some(id) = target_parse(&candidate, &rule, &control) {
+                        found_set.insert(id);
+                    }
+                }
+                // If for some reason a stream isn't caught, this will show if it was overlooked or
+                // somehow failed to be seen at all.
+                // debug!("Stream found and ignored: {}", candidate.id);
             }
-            // If for some reason a stream isn't caught, this will show if it was overlooked or
-            // somehow failed to be seen at all.
-            // debug!("Stream found and ignored: {}", val["id"]);
         }
-        debug!("Starting loop sleep.");
-        sleep(time::Duration::from_secs(120));
+        scheduler.polled(polled_full, polled_priority);
+        check_disk(&control, &mut disk_low);
+        sleep(schedule::TICK);
     }
 
 }
 
-// Usually the stream to download is a YouTube stream with a unique id, but other sources (Twitch)
-// work differently.
-fn target_parse(info: &Value) -> Option<String> {
-    if info["type"] == "stream" {
-        let mut id = info["id"].to_string();
-        id.retain(|c| !c.eq(&'"'));
-        info!("Stream found from api: {}", id);
-        start_stream_loop(id.clone());
-        Some(id)
-    } else if info["placeholderType"] == "external-stream" && info["status"] == "live" {
-        // Generally Twitch, but may pick up Twitter Spaces or other odd ball sources.
-        let mut id = info["link"].to_string();
-        id.retain(|c| !c.eq(&'"'));
-        info!("External stream found from api: {}", id);
-        start_stream_loop(id);
-        Some(info["id"].to_string())
-    } else if info["placeholderType"] == "scheduled-yt-stream" {
-        // Stream is expected based on a posted schedule or some other source, but a waiting room
-        // hasn't been found yet. There is a "certainty" value, but I don't see any way to make it 
-        // relevant, nor is there really anything to do with a stream that doesn't exist yet.
-        None
+// The priority channels get a source of their own, rebuilt whenever the config is reloaded.
+fn priority_source(control: &Control) -> Option<Box<dyn DiscoverySource>> {
+    let channels = control.config.read().unwrap().discovery.priority_channels.clone();
+    match (&control.dex, channels.is_empty()) {
+        (Some(dex), false) => Some(Box::new(HoloDexSource::channels(dex.clone(), channels))),
+        _ => None,
+    }
+}
+
+// Sends a disk_low notification when free space on the download disk drops under the configured
+// amount, and again only once it has recovered and dropped again.
+fn check_disk(control: &Control, disk_low: &mut bool) {
+    let config = control.config.read().unwrap().notify.clone();
+    let path = if Path::new(stream::DOWNLOAD_DIR).exists() { stream::DOWNLOAD_DIR } else { "." };
+    let available = match fs2::available_space(path) {
+        Ok(available) => available,
+        Err(err) => {
+            warn!("Failed to check free disk space: {:?}", err);
+            return;
+        }
+    };
+    let low = available < config.disk_low_gb * 1024 * 1024 * 1024;
+    if low && !*disk_low {
+        warn!("Disk space low: {} GiB free.", available / 1024 / 1024 / 1024);
+        notify::send(&config, Event::new(EventKind::DiskLow, "Disk space low",
+            &format!("{} GiB free in {}", available / 1024 / 1024 / 1024, path)));
+    }
+    *disk_low = low;
+}
+
+// Decides what, if anything, to hand to a StreamManager for a matched candidate. Returns the
+// candidate id if it's been dealt with, i.e. a download was started or it's been skipped for good.
+fn target_parse(candidate: &Candidate, rule: &MatchRule, control: &Arc<Control>) -> Option<String> {
+    // Nothing to record yet, most likely a scheduled placeholder.
+    let target = candidate.target()?;
+
+    if candidate.members_only {
+        let config = control.config.read().unwrap().clone();
+        if !config.membership.channels.contains_key(&candidate.channel_id) {
+            info!("Skipping members-only stream from {} rule, no membership: {} ({})", rule, target,
+                candidate.channel_name);
+            return Some(candidate.id.clone());
+        }
+        // Tried again next pass, in case the cookies are replaced in the meantime.
+        if !config.credentials_for(&candidate.channel_id).iter().any(|c| cookies::check(&c.cookies).usable()) {
+            info!("Holding off on members-only stream {} ({}), no usable cookies.", target, candidate.channel_name);
+            return None;
+        }
+    }
+
+    if candidate.platform == Platform::YouTube {
+        match candidate.scheduled_start {
+            Some(start) if candidate.status == LiveStatus::Upcoming => {
+                info!("Stream found from {} rule: {} ({}), scheduled for {}", rule, target,
+                    candidate.channel_name, start);
+            }
+            _ => {
+                info!("Stream found from {} rule: {} ({})", rule, target, candidate.channel_name);
+            }
+        }
+    } else if candidate.status == LiveStatus::Live || candidate.requested {
+        // Generally Twitch, but may pick up Twitter Spaces or other odd ball sources. Manual targets
+        // are trusted to be worth trying, live or not.
+        info!("External stream found from {} rule: {} ({})", rule, target, candidate.channel_name);
     } else {
         // Most likely, this is an upcoming Twitch stream, but included (and at warn level) to ensure
         // nothing is slipping through.
-        warn!("Stream checked, but failed the target parse: {}", info);
-        None
+        warn!("Stream checked, but failed the target parse: {:?}", candidate);
+        return None;
     }
+    let handle = control.registry.add(candidate, &target, &rule.to_string());
+    start_stream_loop(target, handle, control.clone());
+    Some(candidate.id.clone())
 }
 
 // Function to start a download in a separate thread. Realistically, this is one line of code, but
 // having it split this way makes for easier testing and future changes.
 // TODO: Set up spans for stream threads (probably before the struct is created, in the thread closure).
-fn start_stream_loop(target: String) {
+fn start_stream_loop(target: String, handle: RecordingHandle, control: Arc<Control>) {
+    let config = control.config.read().unwrap().clone();
     thread::spawn(move || {
-        StreamManager::new(target.clone()).unwrap().download_loop();
-        info!("{}: Thread ended.", target);
+        let id = handle.id.clone();
+        let outcome = StreamManager::new(target.clone(), handle, &config).unwrap().download_loop();
+        info!("{}: Thread ended ({:?}).", target, outcome);
+        if outcome == Outcome::Finished {
+            if config.verify.enabled {
+                verify::verify(&id, &config.verify, control.dex.as_ref());
+            }
+            if let Err(err) = catalog::index(&id, control.dex.as_ref()) {
+                error!("{}: Failed to add to the catalog: {:?}", target, err);
+            }
+            if let Some(queue) = control.postprocess.get() {
+                let _ = queue.send(id);
+            }
+        }
     });
 }
 
+#[derive(Parser)]
+#[command(name = "akashic", about = "Automatically records vtuber streams.")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the recorder. This is the default when no subcommand is given.
+    Run,
+    /// Queue urls or video ids for a running recorder to pick up.
+    Enqueue {
+        #[arg(required = true)]
+        targets: Vec<String>,
+    },
+    /// Record a single url or video id in the foreground, without the discovery loop.
+    /// The exit code reflects the outcome: 0 finished, 1 failed, 3 not started, 4 auth failed.
+    Record {
+        target: String,
+        /// Wait for an upcoming stream to start, rather than giving up on it.
+        #[arg(long)]
+        wait: bool,
+        /// Extra yt-dlp options as key=value. Values are read as JSON if possible, else as strings.
+        #[arg(short = 'o', long = "options", value_name = "KEY=VALUE", num_args = 1..)]
+        options: Vec<String>,
+    },
+    /// Check recordings for empty, truncated or corrupt files, and note the results in their
+    /// manifests. Checks the whole archive if no ids are given. Exits with 1 if anything is flagged.
+    Verify {
+        ids: Vec<String>,
+        /// Decode every frame, rather than just reading the packets through.
+        #[arg(long)]
+        full: bool,
+        /// Don't ask HoloDex for stream lengths the info.json is missing.
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Check catalogued recordings upstream, or list the ones that are no longer available.
+    Availability {
+        #[command(subcommand)]
+        command: AvailabilityCommand,
+    },
+    /// Send a test notification to the configured sinks, e.g. to check them against a local server.
+    Notify {
+        /// Which event to pretend happened, for testing per-event filters.
+        #[arg(long, value_enum, default_value_t = EventKind::Finished)]
+        event: EventKind,
+    },
+    /// Check the configured membership cookies (the default ones and each membership's) for a
+    /// YouTube login and when it expires. Exits with 1 if any can't be used.
+    Cookies,
+    /// Search or rebuild the catalog of recordings in "downloads/".
+    Catalog {
+        #[command(subcommand)]
+        command: CatalogCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum AvailabilityCommand {
+    /// Check recordings now (all catalogued ones if no ids are given) and note any changes.
+    Check {
+        ids: Vec<String>,
+        /// "holodex" or "google". Defaults to the configured source.
+        #[arg(long)]
+        source: Option<String>,
+    },
+    /// List recordings that have been privated or removed upstream, i.e. the archive is the only
+    /// copy left.
+    Report,
+}
+
+#[derive(Subcommand)]
+enum CatalogCommand {
+    /// Index everything in the archive, adding HoloDex metadata where it's available.
+    Scan {
+        /// Start from an empty catalog rather than updating the existing one.
+        #[arg(long)]
+        rebuild: bool,
+        /// Only use the local files.
+        #[arg(long)]
+        offline: bool,
+    },
+    /// List recordings matching all the given filters, e.g. --channel pomu --title karaoke --year 2023.
+    Search {
+        /// Channel name (or id).
+        #[arg(long)]
+        channel: Option<String>,
+        #[arg(long)]
+        org: Option<String>,
+        /// Text in the title or the HoloDex topic.
+        #[arg(long)]
+        title: Option<String>,
+        /// The rule the recording was matched by, e.g. "archive" or "keyword".
+        #[arg(long)]
+        rule: Option<String>,
+        /// Streams from this date on (YYYY-MM-DD).
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Streams before this date (YYYY-MM-DD).
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Streams from this year. Shorthand for --from and --to.
+        #[arg(long, conflicts_with_all = ["from", "to"])]
+        year: Option<i32>,
+        /// Only recordings that are no longer available upstream.
+        #[arg(long)]
+        unavailable: bool,
+        /// Only recordings of streams taken down while they were being recorded.
+        #[arg(long)]
+        high_value: bool,
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+}
+
 // 1 sec Miko stream for testing: CAbEy8xAKSE
+// (akashic enqueue CAbEy8xAKSE, rather than editing the code.)
 fn main() -> Result<(), Box<dyn Error>> {
 
     // Sets up a rolling log file.
@@ -194,7 +441,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         .build("logs/")
         .expect("Log file should have been created. Check file paths.");
 
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
     // Set up the logging format layer.
     // By default, ANSI escape characters are included that are illegible in a text reader.
@@ -218,5 +465,195 @@ fn main() -> Result<(), Box<dyn Error>> {
     subscriber::set_global_default(subscriber).unwrap();
     std::panic::set_hook(Box::new(panic_hook));
 
-    api_loop()
+    let code = match Cli::parse().command.unwrap_or(Command::Run) {
+        Command::Run => {
+            let dex_key = match read_file("res/keys/holodex_Key.txt") {
+                Ok(mut file) => {
+                    file.pop_front().unwrap()
+                }
+                Err(err) => {
+                    panic!("Error reading key file: {:?}", err);
+                }
+            };
+            let config = Config::load(CONFIG_PATH)?;
+            let dex = DexClient::new(dex_key, &config.network)?;
+            let sources: Vec<Box<dyn DiscoverySource>> = vec![
+                Box::new(HoloDexSource::new(dex.clone())),
+                Box::new(ManualSource::new(queue::QUEUE_DIR)),
+            ];
+
+            let control = Arc::new(Control::new(config, Some(dex)));
+            control::start(control.clone())?;
+            cookies::start(control.clone());
+            postprocess::start(control.clone());
+            availability::start(control.clone());
+            api_loop(sources, control)?;
+            0
+        }
+        Command::Enqueue { targets } => {
+            for target in targets {
+                let path = queue::enqueue(&target)?;
+                println!("Queued {} ({})", target, path.display());
+            }
+            0
+        }
+        Command::Record { target, wait, options } => {
+            let target = discovery::youtube_id(&target).unwrap_or(target);
+            let config = Config::load(CONFIG_PATH)?;
+            let handle = RecordingHandle::standalone(&target);
+            let id = handle.id.clone();
+            let mut manager = StreamManager::new(target.clone(), handle, &config)?;
+            manager.set_wait(wait);
+            for option in options {
+                let (key, value) = option.split_once('=')
+                    .ok_or_else(|| format!("Options should be formatted as key=value: {}", option))?;
+                let value = serde_json::from_str(value)
+                    .unwrap_or_else(|_| Value::String(value.to_string()));
+                manager.set_option(key, &value)?;
+            }
+            let outcome = manager.download_loop();
+            println!("{}: {:?}", target, outcome);
+            // No queue to hand it to here, so it's done before exiting.
+            if outcome == Outcome::Finished && config.verify.enabled {
+                verify::verify(&id, &config.verify, None);
+            }
+            if outcome == Outcome::Finished && config.postprocess.enabled {
+                postprocess::run(&id, &config);
+            }
+            if outcome == Outcome::Finished {
+                if let Err(err) = catalog::index(&id, None) {
+                    error!("{}: Failed to add to the catalog: {:?}", target, err);
+                }
+            }
+            // Exiting would cut off anything still being sent, and leave hook scripts running on
+            // their own.
+            hooks::wait();
+            notify::flush();
+            outcome.exit_code()
+        }
+        Command::Verify { ids, full, offline } => {
+            let Config { mut verify, network, .. } = Config::load(CONFIG_PATH)?;
+            let config = { verify.full_decode |= full; verify };
+            let dex = match offline {
+                true => None,
+                false => read_file("res/keys/holodex_Key.txt").ok()
+                    .and_then(|mut file| file.pop_front())
+                    .map(|key| DexClient::new(key, &network)).transpose()?,
+            };
+            let ids = if ids.is_empty() { verify::archive_ids() } else { ids };
+            let mut flagged = 0;
+            for id in &ids {
+                let verification = verify::verify(id, &config, dex.as_ref());
+                match (verification.duration, verification.expected) {
+                    (Some(duration), Some(expected)) => println!("{}: {} ({:.0}s of {:.0}s)",
+                        id, verification.status, duration, expected),
+                    (Some(duration), None) => println!("{}: {} ({:.0}s)", id, verification.status, duration),
+                    _ => println!("{}: {}", id, verification.status),
+                }
+                for file in verification.files.iter().filter(|f| f.status != VerifyStatus::Ok) {
+                    match &file.message {
+                        Some(message) => println!("    {}: {} ({})", file.file, file.status, message),
+                        None => println!("    {}: {}", file.file, file.status),
+                    }
+                }
+                if let Some(message) = &verification.message {
+                    println!("    {}", message);
+                }
+                if verification.status != VerifyStatus::Ok {
+                    flagged += 1;
+                }
+            }
+            println!("{} recording(s) checked, {} flagged.", ids.len(), flagged);
+            if flagged > 0 { 1 } else { 0 }
+        }
+        Command::Availability { command: AvailabilityCommand::Check { ids, source } } => {
+            let full_config = Config::load(CONFIG_PATH)?;
+            let config = full_config.availability.clone();
+            let dex = read_file("res/keys/holodex_Key.txt").ok()
+                .and_then(|mut file| file.pop_front())
+                .map(|key| DexClient::new(key, &full_config.network)).transpose()?;
+            let checker = availability::Checker::new(&source.unwrap_or(config.source), dex, &full_config)?;
+            let catalog = Catalog::open(catalog::CATALOG_PATH)?;
+            let ids = if ids.is_empty() { catalog.ids()? } else { ids };
+            let changed = availability::check_all(&catalog, &checker, &ids,
+                time::Duration::from_secs(config.delay_seconds));
+            println!("{} recording(s) checked, {} changed.", ids.len(), changed);
+            0
+        }
+        Command::Availability { command: AvailabilityCommand::Report } => {
+            let lost = Catalog::open(catalog::CATALOG_PATH)?.with_status(&availability::LOST)?;
+            for (entry, status, since) in &lost {
+                println!("{}  {:<20}  {} [{}]  {} since {}  {}", entry.actual_start.or(entry.scheduled_start)
+                    .map(|start| start.format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(|| String::from("----------")),
+                    entry.channel, entry.title, entry.id, status, since.format("%Y-%m-%d %H:%M"),
+                    entry.path.as_deref().unwrap_or("(no file)"));
+            }
+            println!("{} recording(s) only survive in the archive.", lost.len());
+            0
+        }
+        Command::Notify { event } => {
+            let config = Config::load(CONFIG_PATH)?.notify;
+            let event = Event::new(event, "Test notification", "Sent by \"akashic notify\".");
+            let results = notify::deliver(&config, &event);
+            for (sink, result) in &results {
+                match result {
+                    Ok(_) => println!("{}: sent", sink),
+                    Err(err) => println!("{}: failed: {}", sink, err),
+                }
+            }
+            println!("{} sink(s) take {} events.", results.len(), event.event);
+            if results.iter().any(|(_, result)| result.is_err()) { 1 } else { 0 }
+        }
+        Command::Cookies => {
+            let mut unusable = 0;
+            for config in cookies::sources(&Config::load(CONFIG_PATH)?) {
+                let status = cookies::check(&config);
+                println!("{}: {}", cookies::source(&config), status);
+                if !status.usable() {
+                    unusable += 1;
+                }
+            }
+            if unusable > 0 { 1 } else { 0 }
+        }
+        Command::Catalog { command: CatalogCommand::Scan { rebuild, offline } } => {
+            let network = Config::load(CONFIG_PATH)?.network;
+            let dex = match offline {
+                true => None,
+                false => read_file("res/keys/holodex_Key.txt").ok()
+                    .and_then(|mut file| file.pop_front())
+                    .map(|key| DexClient::new(key, &network)).transpose()?,
+            };
+            let catalog = Catalog::open(catalog::CATALOG_PATH)?;
+            let count = catalog::scan(&catalog, dex.as_ref(), rebuild)?;
+            println!("Catalogued {} recording(s) in {}.", count, catalog::CATALOG_PATH);
+            0
+        }
+        Command::Catalog { command: CatalogCommand::Search { channel, org, title, rule, from, to, year, unavailable, high_value, limit } } => {
+            let (from, to) = match year {
+                Some(year) => (NaiveDate::from_ymd_opt(year, 1, 1), NaiveDate::from_ymd_opt(year + 1, 1, 1)),
+                None => (from, to),
+            };
+            let query = catalog::Query { channel, org, title, rule, from, to, unavailable, high_value, limit };
+            let entries = Catalog::open(catalog::CATALOG_PATH)?.search(&query)?;
+            for entry in &entries {
+                let date = entry.actual_start.or(entry.scheduled_start)
+                    .map(|start| start.format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(|| String::from("----------"));
+                let duration = entry.duration
+                    .map(|d| format!("{}:{:02}", (d / 3600.0) as u64, (d % 3600.0 / 60.0) as u64))
+                    .unwrap_or_else(|| String::from("-"));
+                println!("{}  {:<20}  {} [{}]  {}  {:.1} GiB{}{}", date, entry.channel, entry.title, entry.id,
+                    duration, entry.size as f64 / 1024.0 / 1024.0 / 1024.0,
+                    if entry.available == Some(false) { "  (unavailable upstream)" } else { "" },
+                    if entry.high_value { "  (taken down mid-stream)" } else { "" });
+            }
+            println!("{} recording(s).", entries.len());
+            0
+        }
+    };
+
+    // Exiting directly skips destructors, so the log guard has to be flushed by hand.
+    drop(guard);
+    std::process::exit(code)
 }
\ No newline at end of file
@@ -0,0 +1,333 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::config::ConcatConfig;
+use crate::ffmpeg;
+use crate::stream::{recording_files, DOWNLOAD_DIR};
+
+// Per-recording records, kept alongside the downloads so they stay with the archive.
+pub const MANIFEST_DIR: &str = "downloads/manifests";
+
+pub const VIDEO_EXTENSIONS: [&str; 5] = ["ts", "mp4", "mkv", "webm", "flv"];
+
+// One download attempt that actually produced a file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Segment {
+    pub part: u32,
+    pub file: Option<String>,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub bytes: Option<u64>,
+    // Why the attempt ended: "finished", "stall", "retry" and so on.
+    pub ended: String,
+}
+
+// Time between the end of one segment and the start of the next, i.e. what wasn't captured.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Gap {
+    pub after_part: u32,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub seconds: i64,
+}
+
+// What a post-processing step did to one file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepResult {
+    pub step: String,
+    pub input: String,
+    pub output: Option<String>,
+    pub ok: bool,
+    pub message: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+// Ordered from best to worst, so a recording takes the worst of its files.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyStatus {
+    Ok,
+    Truncated,
+    Corrupt,
+    Empty,
+    Missing,
+}
+
+impl Display for VerifyStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyStatus::Ok => write!(f, "ok"),
+            VerifyStatus::Truncated => write!(f, "truncated"),
+            VerifyStatus::Corrupt => write!(f, "corrupt"),
+            VerifyStatus::Empty => write!(f, "empty"),
+            VerifyStatus::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileCheck {
+    pub file: String,
+    pub bytes: u64,
+    pub duration: Option<f64>,
+    pub status: VerifyStatus,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Verification {
+    pub at: DateTime<Utc>,
+    pub status: VerifyStatus,
+    pub files: Vec<FileCheck>,
+    // Total over all files, against what the stream itself is said to have lasted.
+    pub duration: Option<f64>,
+    pub expected: Option<f64>,
+    // "info.json" or "holodex".
+    pub expected_from: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    pub id: String,
+    pub target: String,
+    pub title: String,
+    pub channel: String,
+    #[serde(default)]
+    pub channel_id: String,
+    pub rule: String,
+    // yt-dlp's own id for the video, which is what it names the files after. Not the same as id for
+    // targets given as urls, e.g. Twitch channels.
+    #[serde(default)]
+    pub video_id: Option<String>,
+    // Every file yt-dlp has said it wrote: the parts, their info.json and thumbnails. Paths are as
+    // reported, so may still point into the temporary folder; see locate.
+    #[serde(default)]
+    pub files: Vec<String>,
+    pub segments: Vec<Segment>,
+    pub gaps: Vec<Gap>,
+    // The joined file, once it has been made and checked.
+    pub concatenated: Option<String>,
+    #[serde(default)]
+    pub postprocess: Vec<StepResult>,
+    // The latest check of the files, replaced each time it's run.
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    // How the stream ended upstream, as of the post-check: "ended", "privated" or "deleted".
+    #[serde(default)]
+    pub ended: Option<String>,
+    // Taken down while it was being recorded, so this is likely the only copy.
+    #[serde(default)]
+    pub high_value: bool,
+}
+
+impl Manifest {
+    pub fn path(id: &str) -> PathBuf {
+        // Ids are usually YouTube ids, but manual targets can be whole urls.
+        let name: String = id.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        Path::new(MANIFEST_DIR).join(format!("{}.json", name))
+    }
+
+    // Picks up an existing manifest if this id has been recorded before (e.g. after a restart of the
+    // whole program), so parts keep counting up rather than starting over.
+    pub fn load_or_new(id: &str) -> Manifest {
+        match fs::read_to_string(Self::path(id)) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|err| {
+                warn!("{}: Unreadable manifest, starting a new one: {:?}", id, err);
+                Manifest { id: id.to_string(), ..Default::default() }
+            }),
+            Err(_) => Manifest { id: id.to_string(), ..Default::default() },
+        }
+    }
+
+    pub fn load(id: &str) -> Result<Manifest, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(Self::path(id))?)?)
+    }
+
+    // Written to a temporary file first, so a crash mid-write doesn't lose the old one.
+    pub fn save(&self) {
+        let path = Self::path(&self.id);
+        let result = fs::create_dir_all(MANIFEST_DIR)
+            .and_then(|_| fs::write(path.with_extension("tmp"), serde_json::to_string_pretty(self)?))
+            .and_then(|_| fs::rename(path.with_extension("tmp"), &path));
+        if let Err(err) = result {
+            error!("{}: Failed to save manifest: {:?}", self.id, err);
+        }
+    }
+
+    pub fn last_part(&self) -> u32 {
+        self.segments.last().map(|s| s.part).unwrap_or(0)
+    }
+
+    pub fn add_segment(&mut self, segment: Segment) {
+        if let Some(previous) = self.segments.last() {
+            let from = previous.end.unwrap_or(previous.start);
+            self.gaps.push(Gap {
+                after_part: previous.part,
+                from,
+                to: segment.start,
+                seconds: (segment.start - from).num_seconds(),
+            });
+        }
+        self.segments.push(segment);
+        self.save();
+    }
+
+    // Where a segment's file is now. yt-dlp reports the temporary path while downloading, and moves
+    // the file into the download folder once it's done.
+    pub fn locate(file: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(file);
+        if path.exists() {
+            return Some(path);
+        }
+        let moved = Path::new(DOWNLOAD_DIR).join(path.file_name()?);
+        moved.exists().then_some(moved)
+    }
+
+    // Moves parts left in yt-dlp's temporary folder into the download folder. A download that errors
+    // out never gets moved by yt-dlp, and a partial file of a removed stream is worth keeping.
+    pub fn keep_files(&mut self) {
+        for segment in &mut self.segments {
+            let Some(path) = segment.file.as_deref().and_then(Self::locate) else {
+                continue;
+            };
+            if path.parent() == Some(Path::new(DOWNLOAD_DIR)) {
+                continue;
+            }
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let kept = Path::new(DOWNLOAD_DIR).join(name);
+            match fs::rename(&path, &kept) {
+                Ok(_) => {
+                    info!("{}: Kept partial file {}.", self.id, kept.display());
+                    segment.file = Some(kept.to_string_lossy().to_string());
+                }
+                Err(err) => error!("{}: Failed to move {} into {}: {:?}", self.id, path.display(), DOWNLOAD_DIR, err),
+            }
+        }
+        self.save();
+    }
+
+    // Points whatever referred to a file that has since been replaced, e.g. by a remux, at the new one.
+    // Goes by name, the same as locate, since the old file is usually gone by now.
+    pub fn replace_file(&mut self, old: &Path, new: &Path) {
+        let new = new.to_string_lossy().to_string();
+        let replaced = |file: &str| Path::new(file).file_name() == old.file_name();
+        let files = self.segments.iter_mut().map(|s| &mut s.file)
+            .chain(std::iter::once(&mut self.concatenated));
+        for file in files {
+            if file.as_deref().is_some_and(replaced) {
+                *file = Some(new.clone());
+            }
+        }
+        for file in &mut self.files {
+            if replaced(file) {
+                *file = new.clone();
+            }
+        }
+        self.save();
+    }
+
+    // Everything on disk for the recording: the files yt-dlp reported, wherever they are now, and
+    // whatever else is named after the video or the recording (chat, post-processed copies, the joined
+    // file, and recordings from before files were kept track of).
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.files.iter().filter_map(|file| Self::locate(file)).collect();
+        let named = self.video_id.iter().chain(std::iter::once(&self.id)).flat_map(|id| recording_files(id));
+        for file in named {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files.sort();
+        files
+    }
+
+    // The recording as it stands: the joined file if there is one, otherwise each part, otherwise
+    // whatever video files there are for it (e.g. recordings from before manifests were kept).
+    pub fn video_files(&self) -> Vec<PathBuf> {
+        if let Some(joined) = self.concatenated.as_deref().and_then(Self::locate) {
+            return vec![joined];
+        }
+        let parts: Vec<PathBuf> = self.segments.iter()
+            .filter_map(|s| s.file.as_deref().and_then(Self::locate))
+            .collect();
+        if !parts.is_empty() {
+            return parts;
+        }
+        self.files().into_iter()
+            .filter(|f| f.extension().is_some_and(|ext| VIDEO_EXTENSIONS.iter().any(|v| ext == *v)))
+            .collect()
+    }
+
+    // info.json files yt-dlp wrote for the recording, the ones next to its video files first.
+    pub fn info_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.video_files().iter()
+            .map(|file| file.with_extension("info.json"))
+            .filter(|path| path.exists())
+            .collect();
+        for file in self.files() {
+            if file.to_string_lossy().ends_with(".info.json") && !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    // Joins the .ts segments into one file. The parts are only removed (if at all) once the joined
+    // file checks out against them.
+    pub fn concatenate(&mut self, config: &ConcatConfig) -> Result<(), Box<dyn Error>> {
+        let files: Vec<PathBuf> = self.segments.iter()
+            .filter_map(|s| s.file.as_deref().and_then(Self::locate))
+            .collect();
+        if files.len() < 2 {
+            return Ok(());
+        }
+        if files.len() != self.segments.len() {
+            return Err("Some segment files are missing, not concatenating.".into());
+        }
+        if files.iter().any(|f| f.extension().is_none_or(|ext| ext != "ts")) {
+            return Err("Only .ts segments can be joined losslessly, not concatenating.".into());
+        }
+
+        let first = &files[0];
+        let stem = first.file_stem().unwrap_or_default().to_string_lossy();
+        let output = first.with_file_name(format!("{} (joined).ts", stem));
+        let refs: Vec<&Path> = files.iter().map(PathBuf::as_path).collect();
+        ffmpeg::concat(&refs, &output)?;
+
+        // A lossless join should come out the same length as the parts, give or take the odd frame
+        // at each boundary.
+        let expected: f64 = files.iter()
+            .map(|f| ffmpeg::probe_duration(f))
+            .collect::<Result<Vec<f64>, _>>()?
+            .iter().sum();
+        let actual = ffmpeg::probe_duration(&output)?;
+        if (expected - actual).abs() > 1.0 + files.len() as f64 {
+            return Err(format!("Joined file is {:.1}s but the parts add up to {:.1}s, keeping the parts.",
+                actual, expected).into());
+        }
+
+        info!("{}: Joined {} parts into {}", self.id, files.len(), output.display());
+        self.concatenated = Some(output.to_string_lossy().to_string());
+        self.save();
+
+        if config.delete_parts {
+            for file in &files {
+                if let Err(err) = fs::remove_file(file) {
+                    warn!("{}: Failed to remove part {}: {:?}", self.id, file.display(), err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use crate::discovery::Candidate;
+
+// Which rule caused a stream to be recorded. Mostly for logging now, but it's useful information to
+// keep around.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatchRule {
+    Archive,
+    Keyword(String),
+    Unarchived,
+    Manual,
+}
+
+impl fmt::Display for MatchRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchRule::Archive => write!(f, "archive"),
+            MatchRule::Keyword(word) => write!(f, "keyword:{}", word),
+            MatchRule::Unarchived => write!(f, "unarchived"),
+            MatchRule::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+impl MatchRule {
+    // Which kind of rule it is, without the keyword. Keywords come from a list anyone can edit, so
+    // they're no good as a metric label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchRule::Archive => "archive",
+            MatchRule::Keyword(_) => "keyword",
+            MatchRule::Unarchived => "unarchived",
+            MatchRule::Manual => "manual",
+        }
+    }
+}
+
+// Decides whether a candidate is worth recording, independent of where the candidate came from.
+pub struct Matcher {
+    archive_set: HashSet<String>,
+    check_set: HashSet<String>,
+    key_word_set: VecDeque<String>,
+}
+
+impl Matcher {
+    pub fn new(archive_set: HashSet<String>,
+               check_set: HashSet<String>,
+               key_word_set: VecDeque<String>) -> Self {
+        Matcher {
+            archive_set,
+            check_set,
+            key_word_set,
+        }
+    }
+
+    // Whether the channel is one of ours, i.e. in the archive or check list.
+    pub fn watches(&self, channel_id: &str) -> bool {
+        self.archive_set.contains(channel_id) || self.check_set.contains(channel_id)
+    }
+
+    pub fn check(&self, candidate: &Candidate) -> Option<MatchRule> {
+        if candidate.requested {
+            return Some(MatchRule::Manual);
+        }
+        if self.archive_set.contains(&candidate.channel_id) {
+            return Some(MatchRule::Archive);
+        }
+
+        // Titles are made lowercase and stripped of whitespace before checking, see the ReadMe.
+        let mut title = candidate.title.to_lowercase();
+        title.retain(|c| !c.is_whitespace());
+
+        if self.check_set.contains(&candidate.channel_id) {
+            for word in &self.key_word_set {
+                if title.contains(word.as_str()) {
+                    return Some(MatchRule::Keyword(word.clone()));
+                }
+            }
+        }
+
+        if title.contains("unarchived") {
+            return Some(MatchRule::Unarchived);
+        }
+        None
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::control::Control;
+use crate::quota;
+use crate::registry::RecordingState;
+use crate::stream::DOWNLOAD_DIR;
+
+// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+}
+
+pub static HOLODEX_POLLS: Metric = Metric {
+    name: "akashic_holodex_polls_total",
+    help: "HoloDex live polls, by result.",
+    kind: "counter",
+};
+pub static HOLODEX_LATENCY: Metric = Metric {
+    name: "akashic_holodex_poll_seconds",
+    help: "How long HoloDex live polls took.",
+    kind: "histogram",
+};
+pub static MATCHES: Metric = Metric {
+    name: "akashic_matched_streams_total",
+    help: "Streams matched for recording, by kind of rule.",
+    kind: "counter",
+};
+pub static YTDLP_ERRORS: Metric = Metric {
+    name: "akashic_ytdlp_errors_total",
+    help: "Errors from yt-dlp download attempts, by kind.",
+    kind: "counter",
+};
+pub static GOOGLE_CALLS: Metric = Metric {
+    name: "akashic_google_api_calls_total",
+    help: "YouTube Data API calls, by result.",
+    kind: "counter",
+};
+pub static GOOGLE_QUOTA: Metric = Metric {
+    name: "akashic_google_api_quota_units_total",
+    help: "Estimated YouTube Data API quota used.",
+    kind: "counter",
+};
+
+// A metric's values, by rendered label set, in the order they first turned up. Keeps histogram
+// buckets in order without having to sort them.
+type Series = Vec<(String, f64)>;
+
+fn value(series: &mut Series, labels: String) -> &mut f64 {
+    let index = match series.iter().position(|(l, _)| *l == labels) {
+        Some(index) => index,
+        None => {
+            series.push((labels, 0.0));
+            series.len() - 1
+        }
+    };
+    &mut series[index].1
+}
+
+// Everything counted so far, by metric name. Gauges that can be read off the recorder's own state
+// (recordings, disk) aren't kept here, they're worked out per scrape.
+static SERIES: Mutex<BTreeMap<&'static str, (&'static Metric, Series)>> = Mutex::new(BTreeMap::new());
+
+impl Metric {
+    pub fn inc(&'static self, labels: &[(&str, &str)]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn add(&'static self, labels: &[(&str, &str)], amount: f64) {
+        let mut series = SERIES.lock().unwrap();
+        let (_, values) = series.entry(self.name).or_insert_with(|| (self, Vec::new()));
+        *value(values, render_labels(labels)) += amount;
+    }
+
+    // Records one observation into the histogram buckets, plus the sum and count.
+    pub fn observe(&'static self, observed: f64) {
+        let mut series = SERIES.lock().unwrap();
+        let (_, values) = series.entry(self.name).or_insert_with(|| (self, Vec::new()));
+        for bound in BUCKETS {
+            *value(values, format!("_bucket{{le=\"{}\"}}", bound)) += if observed <= bound { 1.0 } else { 0.0 };
+        }
+        *value(values, String::from("_bucket{le=\"+Inf\"}")) += 1.0;
+        *value(values, String::from("_sum")) += observed;
+        *value(values, String::from("_count")) += 1.0;
+    }
+}
+
+// Label values are escaped as the text format asks: backslashes, quotes and newlines.
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels.iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key,
+            value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// The Prometheus text format for everything above, plus the recorder's current state.
+pub fn render(control: &Control) -> String {
+    let mut out = String::new();
+    for (name, (metric, values)) in SERIES.lock().unwrap().iter() {
+        header(&mut out, name, metric.help, metric.kind);
+        for (labels, value) in values {
+            // Histogram series carry their own suffix; everything else starts with its labels.
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    }
+
+    let recordings = control.registry.list();
+    header(&mut out, "akashic_recordings", "Recordings this run knows about, by state.", "gauge");
+    for (state, label) in [(RecordingState::Starting, "starting"), (RecordingState::Waiting, "waiting"),
+                           (RecordingState::Recording, "recording"), (RecordingState::Done, "done")] {
+        let count = recordings.iter().filter(|r| r.state == state).count();
+        let _ = writeln!(out, "akashic_recordings{{state=\"{}\"}} {}", label, count);
+    }
+    header(&mut out, "akashic_recording_downloaded_bytes",
+        "Bytes downloaded by the current attempt of each unfinished recording, as yt-dlp reports it.", "gauge");
+    for recording in recordings.iter().filter(|r| r.state != RecordingState::Done) {
+        if let Some(bytes) = recording.progress().downloaded_bytes {
+            let _ = writeln!(out, "akashic_recording_downloaded_bytes{} {}",
+                render_labels(&[("id", &recording.id), ("channel", &recording.channel)]), bytes);
+        }
+    }
+
+    let usage = quota::usage();
+    header(&mut out, "akashic_google_api_quota_used", "YouTube Data API quota used today, Pacific time.", "gauge");
+    let _ = writeln!(out, "akashic_google_api_quota_used {}", usage.used);
+    header(&mut out, "akashic_google_api_quota_budget", "Daily YouTube Data API quota budget.", "gauge");
+    let _ = writeln!(out, "akashic_google_api_quota_budget {}", control.config.read().unwrap().google.daily_budget);
+
+    let path = if Path::new(DOWNLOAD_DIR).exists() { DOWNLOAD_DIR } else { "." };
+    if let Ok(available) = fs2::available_space(path) {
+        header(&mut out, "akashic_disk_free_bytes", "Free space on the download disk.", "gauge");
+        let _ = writeln!(out, "akashic_disk_free_bytes {}", available);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(render_labels(&[]), "");
+        assert_eq!(render_labels(&[("rule", "archive"), ("id", "abc")]), "{rule=\"archive\",id=\"abc\"}");
+        assert_eq!(render_labels(&[("channel", "a \"b\"\\c\nd")]), "{channel=\"a \\\"b\\\"\\\\c\\nd\"}");
+    }
+}
@@ -0,0 +1,242 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::Write;
+use std::mem;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use lettre::{Message, SmtpTransport, Transport};
+use lettre::transport::smtp::authentication::Credentials;
+use reqwest::blocking;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::config::NotifyConfig;
+use crate::manifest::Manifest;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Notifications still being sent in the background, so a command that exits once it's done can
+// wait for them first.
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum EventKind {
+    Started,
+    Finished,
+    Failed,
+    AuthFailed,
+    // Something a person should look at, like an error message the recorder doesn't know.
+    Error,
+    DiskLow,
+    HolodexUnreachable,
+    // The membership cookies have run out, are about to, or were turned down.
+    CookieExpiry,
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Started => write!(f, "started"),
+            EventKind::Finished => write!(f, "finished"),
+            EventKind::Failed => write!(f, "failed"),
+            EventKind::AuthFailed => write!(f, "auth_failed"),
+            EventKind::Error => write!(f, "error"),
+            EventKind::DiskLow => write!(f, "disk_low"),
+            EventKind::HolodexUnreachable => write!(f, "holodex_unreachable"),
+            EventKind::CookieExpiry => write!(f, "cookie_expiry"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    pub summary: String,
+    pub detail: String,
+    pub id: Option<String>,
+    pub channel: Option<String>,
+    pub title: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+impl Event {
+    pub fn new(event: EventKind, summary: &str, detail: &str) -> Event {
+        Event {
+            event,
+            summary: summary.to_string(),
+            detail: detail.to_string(),
+            id: None,
+            channel: None,
+            title: None,
+            time: Utc::now(),
+        }
+    }
+
+    // An event about one recording. The detail defaults to who and what it is.
+    pub fn recording(event: EventKind, summary: &str, manifest: &Manifest, detail: Option<&str>) -> Event {
+        let title = if manifest.title.is_empty() { &manifest.target } else { &manifest.title };
+        let detail = match detail {
+            Some(detail) => format!("{}: {} ({})\n{}", manifest.channel, title, manifest.id, detail),
+            None => format!("{}: {} ({})", manifest.channel, title, manifest.id),
+        };
+        Event {
+            id: Some(manifest.id.clone()),
+            channel: Some(manifest.channel.clone()).filter(|c| !c.is_empty()),
+            title: Some(title.clone()),
+            ..Event::new(event, summary, &detail)
+        }
+    }
+}
+
+// Where notifications go. Each sink can be limited to some events; no list means all of them.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub sink: Sink,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sink {
+    // Posts JSON with both "content" (Discord) and "text" (Slack), plus the event itself.
+    Webhook { url: String },
+    // ntfy topic url, e.g. "https://ntfy.sh/my-topic".
+    Ntfy { url: String, token: Option<String> },
+    // Gotify server url; "/message" is added.
+    Gotify { url: String, token: String },
+    Email {
+        server: String,
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    // Runs a local command with the event as JSON on stdin.
+    Command { command: Vec<String> },
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+impl Sink {
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Webhook { .. } => "webhook",
+            Sink::Ntfy { .. } => "ntfy",
+            Sink::Gotify { .. } => "gotify",
+            Sink::Email { .. } => "email",
+            Sink::Command { .. } => "command",
+        }
+    }
+
+    fn send(&self, event: &Event) -> Result<(), Box<dyn Error>> {
+        let client = || blocking::Client::builder().timeout(HTTP_TIMEOUT).build();
+        match self {
+            Sink::Webhook { url } => {
+                let text = format!("**{}**\n{}", event.summary, event.detail);
+                client()?.post(url)
+                    .json(&json!({"content": text, "text": text, "event": event}))
+                    .send()?.error_for_status()?;
+            }
+            Sink::Ntfy { url, token } => {
+                let mut request = client()?.post(url)
+                    .header("Title", &event.summary)
+                    .header("Tags", event.event.to_string())
+                    .body(event.detail.clone());
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                request.send()?.error_for_status()?;
+            }
+            Sink::Gotify { url, token } => {
+                client()?.post(format!("{}/message", url.trim_end_matches('/')))
+                    .header("X-Gotify-Key", token)
+                    .json(&json!({"title": event.summary, "message": event.detail, "priority": 5}))
+                    .send()?.error_for_status()?;
+            }
+            Sink::Email { server, port, username, password, from, to } => {
+                let mut message = Message::builder()
+                    .from(from.parse()?)
+                    .subject(format!("[akashic] {}", event.summary));
+                for address in to {
+                    message = message.to(address.parse()?);
+                }
+                let message = message.body(event.detail.clone())?;
+                let mut transport = SmtpTransport::starttls_relay(server)?.port(*port);
+                if let (Some(username), Some(password)) = (username, password) {
+                    transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+                transport.build().send(&message)?;
+            }
+            Sink::Command { command } => {
+                let (program, args) = command.split_first().ok_or("Empty notification command.")?;
+                let mut child = Command::new(program)
+                    .args(args)
+                    .env("AKASHIC_EVENT", event.event.to_string())
+                    .env("AKASHIC_SUMMARY", &event.summary)
+                    .env("AKASHIC_DETAIL", &event.detail)
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(serde_json::to_string(event)?.as_bytes())?;
+                }
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(format!("Notification command exited with {}", status).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Delivers an event to every sink that wants it, in the background so a slow or unreachable sink
+// never holds up a recording.
+pub fn send(config: &NotifyConfig, event: Event) {
+    if config.sinks.is_empty() {
+        return;
+    }
+    let config = config.clone();
+    let handle = thread::spawn(move || {
+        for (sink, result) in deliver(&config, &event) {
+            if let Err(err) = result {
+                warn!("Failed to send {} notification to {}: {}", event.event, sink, err);
+            }
+        }
+    });
+    let mut pending = PENDING.lock().unwrap();
+    pending.retain(|handle| !handle.is_finished());
+    pending.push(handle);
+}
+
+// Waits for notifications sent in the background to go out, e.g. before exiting.
+pub fn flush() {
+    for handle in mem::take(&mut *PENDING.lock().unwrap()) {
+        let _ = handle.join();
+    }
+}
+
+// Delivers an event to every sink that wants it, returning how each went.
+pub fn deliver(config: &NotifyConfig, event: &Event) -> Vec<(&'static str, Result<(), String>)> {
+    let kind = event.event.to_string();
+    config.sinks.iter()
+        .filter(|sink| sink.events.is_empty() || sink.events.contains(&kind))
+        .map(|sink| {
+            debug!("Sending {} notification to {}.", kind, sink.sink.name());
+            (sink.sink.name(), sink.sink.send(event).map_err(|err| err.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::catalog;
+use crate::config::{Config, PostProcessConfig};
+use crate::control::Control;
+use crate::ffmpeg;
+use crate::hooks::{self, Hook};
+use crate::manifest::{Manifest, StepResult};
+use crate::registry::RecordingState;
+
+// How often a queued job checks whether the recorders have gone quiet.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+const THUMBNAIL_EXTENSIONS: [&str; 3] = ["jpg", "png", "webp"];
+
+// Starts the background queue. Finished recordings are sent here by id and handled one at a time,
+// with whatever the config says at the time they come up.
+pub fn start(control: Arc<Control>) {
+    let (sender, receiver) = mpsc::channel::<String>();
+    if control.postprocess.set(sender).is_err() {
+        warn!("Post-processing queue already started.");
+        return;
+    }
+    thread::spawn(move || {
+        for id in receiver {
+            let config = control.config.read().unwrap().postprocess.clone();
+            if !config.enabled {
+                debug!("{}: Post-processing disabled, skipping.", id);
+                continue;
+            }
+            if config.idle_only {
+                while control.registry.list().iter().any(|r| r.state == RecordingState::Recording) {
+                    debug!("{}: Waiting for recordings to finish before post-processing.", id);
+                    thread::sleep(IDLE_CHECK_INTERVAL);
+                }
+            }
+            run(&id, &control.config.read().unwrap().clone());
+        }
+    });
+}
+
+// Runs the configured steps over a finished recording's files and records each result in its
+// manifest. A failed step only stops the steps after it for that file.
+pub fn run(id: &str, full_config: &Config) {
+    let config = &full_config.postprocess;
+    let mut manifest = Manifest::load_or_new(id);
+    let files = manifest.video_files();
+    if files.is_empty() {
+        warn!("{}: No files to post-process.", id);
+        return;
+    }
+    let profile = config.channels.get(&manifest.channel_id)
+        .and_then(|name| config.profiles.get(name).map(|profile| (name, profile)));
+
+    info!("{}: Post-processing {} file(s).", id, files.len());
+    let mut replaced = false;
+    for file in files {
+        let mut current = file.clone();
+
+        if config.probe && !record(&mut manifest, "probe", &file, probe(&file)) {
+            continue;
+        }
+
+        if let Some(format) = &config.remux {
+            let result = remux(&file, format, config);
+            let remuxed = result.as_ref().ok().and_then(|output| output.clone());
+            if !record(&mut manifest, "remux", &file, result) {
+                continue;
+            }
+            if let Some(remuxed) = remuxed {
+                if !config.keep_original {
+                    let removed = fs::remove_file(&file)
+                        .map(|_| None)
+                        .map_err(|err| err.into());
+                    if record(&mut manifest, "remove_original", &file, removed) {
+                        // The parts are what verify, the catalog and the files endpoint go by.
+                        manifest.replace_file(&file, &remuxed);
+                        replaced = true;
+                    }
+                }
+                current = remuxed;
+            }
+        }
+
+        if let Some((name, profile)) = profile {
+            let output = current.with_file_name(format!("{} ({}).{}",
+                current.file_stem().unwrap_or_default().to_string_lossy(), name, profile.extension));
+            let mut args: Vec<OsString> = vec!["-i".into(), current.clone().into()];
+            args.extend(profile.args.iter().map(OsString::from));
+            args.push(output.clone().into());
+            let result = ffmpeg::run(&args, config.niceness).map(|_| Some(output));
+            record(&mut manifest, "transcode", &current, result);
+        }
+    }
+    manifest.save();
+    // The catalog was filled in before post-processing, with the path of the file that's now gone.
+    if replaced {
+        if let Err(err) = catalog::index(id, None) {
+            warn!("{}: Failed to update the catalog: {:?}", id, err);
+        }
+    }
+    hooks::run(&full_config.hooks, Hook::PostProcessed, &manifest, None);
+}
+
+// Adds a step's result to the manifest, returning whether it succeeded.
+fn record(manifest: &mut Manifest, step: &str, input: &Path, result: Result<Option<PathBuf>, Box<dyn Error>>) -> bool {
+    let (ok, output, message) = match result {
+        Ok(output) => (true, output, None),
+        Err(err) => {
+            warn!("{}: Post-processing step {} failed on {}: {}", manifest.id, step, input.display(), err);
+            (false, None, Some(err.to_string()))
+        }
+    };
+    manifest.postprocess.push(StepResult {
+        step: step.to_string(),
+        input: input.to_string_lossy().to_string(),
+        output: output.map(|o| o.to_string_lossy().to_string()),
+        ok,
+        message,
+        at: Utc::now(),
+    });
+    ok
+}
+
+fn probe(file: &Path) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if fs::metadata(file)?.len() == 0 {
+        return Err("File is empty.".into());
+    }
+    let duration = ffmpeg::probe_duration(file)?;
+    if duration <= 0.0 {
+        return Err(format!("Reported duration is {}s.", duration).into());
+    }
+    Ok(None)
+}
+
+// Copies the streams into a new container, adding the thumbnail and tags yt-dlp left next to the
+// file. Returns the new file, or None if there was nothing to do.
+fn remux(file: &Path, format: &str, config: &PostProcessConfig) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if file.extension().is_some_and(|ext| ext == format) {
+        return Ok(None);
+    }
+    let output = file.with_extension(format);
+    if output.exists() {
+        return Err(format!("{} already exists.", output.display()).into());
+    }
+
+    let mut args: Vec<OsString> = vec!["-i".into(), file.into()];
+    let thumbnail = config.thumbnail.then(|| sidecar(file, &THUMBNAIL_EXTENSIONS)).flatten();
+    match (format, &thumbnail) {
+        ("mkv", Some(thumbnail)) => {
+            let extension = thumbnail.extension().unwrap_or_default().to_string_lossy().to_string();
+            let mime = if extension == "jpg" { String::from("image/jpeg") } else { format!("image/{}", extension) };
+            args.extend(["-map".into(), "0".into(), "-attach".into(), thumbnail.into()]);
+            args.extend(["-metadata:s:t".into(), format!("mimetype={}", mime).into()]);
+            args.extend(["-metadata:s:t".into(), format!("filename=cover.{}", extension).into()]);
+        }
+        // mp4 only takes jpg and png covers, as a second video stream.
+        ("mp4", Some(thumbnail)) if thumbnail.extension().is_some_and(|ext| ext != "webp") => {
+            args.extend(["-i".into(), thumbnail.into(), "-map".into(), "0".into(), "-map".into(), "1".into()]);
+            args.extend(["-disposition:v:1".into(), "attached_pic".into()]);
+        }
+        _ => args.extend(["-map".into(), "0".into()]),
+    }
+    if config.metadata {
+        for (key, value) in tags(file) {
+            args.extend(["-metadata".into(), format!("{}={}", key, value).into()]);
+        }
+    }
+    args.extend(["-c".into(), "copy".into(), output.clone().into()]);
+    ffmpeg::run(&args, config.niceness)?;
+
+    // Same check as for joined files: a remux shouldn't change the length.
+    let expected = ffmpeg::probe_duration(file)?;
+    let actual = ffmpeg::probe_duration(&output)?;
+    if (expected - actual).abs() > 1.0 {
+        return Err(format!("Remuxed file is {:.1}s but the original is {:.1}s.", actual, expected).into());
+    }
+    Ok(Some(output))
+}
+
+// A file yt-dlp wrote alongside the video, e.g. "title [id].webp" for "title [id].ts".
+pub fn sidecar(file: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    extensions.iter()
+        .map(|ext| file.with_extension(ext))
+        .find(|path| path.exists())
+}
+
+// Container tags from the info.json, if there is one.
+fn tags(file: &Path) -> Vec<(&'static str, String)> {
+    let info: Value = match sidecar(file, &["info.json"])
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str(&text).ok()) {
+        Some(info) => info,
+        None => return Vec::new(),
+    };
+    let text = |key: &str| info.get(key).and_then(Value::as_str).map(str::to_string);
+    let mut tags = Vec::new();
+    if let Some(title) = text("title") {
+        tags.push(("title", title));
+    }
+    if let Some(channel) = text("channel").or_else(|| text("uploader")) {
+        tags.push(("artist", channel));
+    }
+    // yt-dlp dates are YYYYMMDD.
+    if let Some(date) = text("release_date").or_else(|| text("upload_date")) {
+        if date.len() == 8 {
+            tags.push(("date", format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..])));
+        }
+    }
+    if let Some(url) = text("webpage_url") {
+        tags.push(("comment", url));
+    }
+    if let Some(description) = text("description") {
+        tags.push(("description", description));
+    }
+    tags
+}
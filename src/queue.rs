@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info, warn};
+
+use crate::discovery::{Candidate, DiscoverySource};
+
+// Drop-folder for manually requested recordings. Any ".txt" file placed here is read on the next
+// pass of the discovery loop, one url or video id per line, and then removed.
+pub const QUEUE_DIR: &str = "res/queue";
+
+// Used by the "enqueue" subcommand to hand a target to a running recorder.
+// The file is written under a temporary name and renamed so the recorder never reads half a file.
+pub fn enqueue(target: &str) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(QUEUE_DIR)?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let temp = Path::new(QUEUE_DIR).join(format!("manual-{}.tmp", stamp));
+    let path = temp.with_extension("txt");
+    fs::write(&temp, format!("{}\n", target.trim()))?;
+    fs::rename(&temp, &path)?;
+    Ok(path)
+}
+
+// Discovery source for manually requested targets. These skip the matcher entirely, but otherwise go
+// through the same found set and StreamManager lifecycle as anything else.
+pub struct ManualSource {
+    dir: PathBuf,
+}
+
+impl ManualSource {
+    pub fn new(dir: &str) -> Self {
+        ManualSource {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+impl DiscoverySource for ManualSource {
+    fn name(&self) -> &str {
+        "Manual queue"
+    }
+
+    fn poll(&mut self) -> Result<Vec<Candidate>, Box<dyn Error>> {
+        if !self.dir.exists() {
+            fs::create_dir_all(&self.dir)?;
+        }
+
+        let mut candidates = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "txt") {
+                continue;
+            }
+
+            match fs::read_to_string(&path) {
+                Ok(file) => {
+                    for line in file.lines().map(str::trim)
+                        .filter(|l| !l.is_empty() && !l.starts_with('#')) {
+                        info!("Manual target queued from {}: {}", path.display(), line);
+                        candidates.push(Candidate::from_target(line));
+                    }
+                }
+                Err(err) => {
+                    error!("Error reading queue file {}: {:?}", path.display(), err);
+                    continue;
+                }
+            }
+
+            // Removing the file is what marks it as handled; if this fails the targets will be seen
+            // again next pass, but the found set will catch them.
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Failed to remove queue file {}: {:?}", path.display(), err);
+            }
+        }
+        Ok(candidates)
+    }
+}
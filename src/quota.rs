@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::sync::Mutex;
+
+use chrono::{NaiveDate, Utc};
+use chrono_tz::America::Los_Angeles;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::metrics;
+
+// Survives restarts, so a crash loop can't spend the day's quota over again.
+pub const QUOTA_PATH: &str = "res/google_quota.json";
+
+// Quota units per YouTube Data API call, from the API's quota calculator. Anything not listed costs
+// the usual single unit.
+const COSTS: [(&str, u64); 5] = [
+    ("videos.list", 1),
+    ("channels.list", 1),
+    ("playlistItems.list", 1),
+    ("liveBroadcasts.list", 1),
+    ("search.list", 100),
+];
+
+pub fn cost(method: &str) -> u64 {
+    COSTS.iter().find(|(m, _)| *m == method).map_or(1, |(_, cost)| *cost)
+}
+
+// The day's usage. Google resets quotas at midnight Pacific time, so that's what the day is.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Usage {
+    pub day: NaiveDate,
+    pub used: u64,
+}
+
+static USAGE: Mutex<Option<Usage>> = Mutex::new(None);
+
+fn today() -> NaiveDate {
+    Utc::now().with_timezone(&Los_Angeles).date_naive()
+}
+
+// Returned instead of making a call that would go over the budget.
+#[derive(Debug)]
+pub struct Exhausted {
+    pub used: u64,
+    pub budget: u64,
+}
+
+impl Display for Exhausted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Google API budget used up for today ({} of {} units).", self.used, self.budget)
+    }
+}
+
+impl Error for Exhausted {}
+
+// Today's usage, starting again from zero once the day has turned over.
+pub fn usage() -> Usage {
+    let mut usage = USAGE.lock().unwrap();
+    current(&mut usage).clone()
+}
+
+fn current(usage: &mut Option<Usage>) -> &mut Usage {
+    let stored = usage.take()
+        .or_else(|| fs::read_to_string(QUOTA_PATH).ok().and_then(|text| serde_json::from_str(&text).ok()));
+    let usage = usage.insert(stored.unwrap_or(Usage { day: today(), used: 0 }));
+    if usage.day != today() {
+        *usage = Usage { day: today(), used: 0 };
+    }
+    usage
+}
+
+// Books the cost of a call against the day's budget, or refuses it if that would go over.
+pub fn spend(method: &str, budget: u64) -> Result<(), Exhausted> {
+    let cost = cost(method);
+    let mut guard = USAGE.lock().unwrap();
+    let usage = current(&mut guard);
+    if usage.used + cost > budget {
+        warn!("Not calling {}: {}", method, Exhausted { used: usage.used, budget });
+        return Err(Exhausted { used: usage.used, budget });
+    }
+    usage.used += cost;
+    metrics::GOOGLE_QUOTA.add(&[], cost as f64);
+    let saved = serde_json::to_string(&*usage).map_err(Box::<dyn Error>::from)
+        .and_then(|json| Ok(fs::write(QUOTA_PATH, json)?));
+    if let Err(err) = saved {
+        error!("Failed to save Google quota usage: {:?}", err);
+    }
+    Ok(())
+}
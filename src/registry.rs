@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+
+use crate::discovery::Candidate;
+use crate::stream::Outcome;
+
+// Snapshot of what the yt-dlp hooks last reported for a recording. Written from the Python side
+// (under the GIL) and read from anywhere else, so it's kept behind a plain mutex rather than in the
+// PyStruct itself.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Progress {
+    pub youtube: bool,
+    // yt-dlp's own id for the video, which is what goes in the file names. Not the same as the
+    // recording's id for targets given as urls, e.g. Twitch channels.
+    pub video_id: Option<String>,
+    pub live_status: Option<String>,
+    pub status: Option<String>,
+    // First time the progress hook was called, i.e. when data actually started coming in.
+    pub started: Option<DateTime<Utc>>,
+    pub updated: Option<DateTime<Utc>>,
+    pub downloaded_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    // Bytes per second, as yt-dlp calculates it.
+    pub speed: Option<f64>,
+    // Seconds since yt-dlp started this particular download call.
+    pub elapsed: Option<f64>,
+    pub fragment_index: Option<u64>,
+    pub fragment_count: Option<u64>,
+    pub filename: Option<String>,
+    // Every file yt-dlp has said it wrote, over all attempts.
+    pub files: Vec<String>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        write!(f, "{:.1} MiB", mib(self.downloaded_bytes.unwrap_or(0)))?;
+        if let Some(total) = self.total_bytes {
+            write!(f, " of {:.1} MiB", mib(total))?;
+        }
+        if let Some(speed) = self.speed {
+            write!(f, " at {:.2} MiB/s", speed / (1024.0 * 1024.0))?;
+        }
+        if let Some(index) = self.fragment_index {
+            match self.fragment_count {
+                Some(count) => write!(f, ", fragment {}/{}", index, count)?,
+                None => write!(f, ", fragment {}", index)?,
+            }
+        }
+        if let Some(elapsed) = self.elapsed {
+            write!(f, ", {:.0}s elapsed", elapsed)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingState {
+    // Found, but the thread hasn't made an attempt yet.
+    Starting,
+    // Upcoming; sleeping until the next attempt.
+    Waiting,
+    // A yt-dlp download call is in progress.
+    Recording,
+    // The download loop has ended, see the outcome.
+    Done,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Recording {
+    pub id: String,
+    pub target: String,
+    pub title: String,
+    pub channel: String,
+    pub channel_id: String,
+    pub rule: String,
+    pub scheduled_start: Option<DateTime<Utc>>,
+    pub members_only: bool,
+    pub state: RecordingState,
+    pub outcome: Option<Outcome>,
+    pub found: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    #[serde(skip)]
+    cancel: Arc<AtomicBool>,
+    #[serde(serialize_with = "snapshot")]
+    progress: Arc<Mutex<Progress>>,
+}
+
+fn snapshot<S: Serializer>(progress: &Arc<Mutex<Progress>>, serializer: S) -> Result<S::Ok, S::Error> {
+    progress.lock().unwrap().serialize(serializer)
+}
+
+impl Recording {
+    pub fn progress(&self) -> Progress {
+        self.progress.lock().unwrap().clone()
+    }
+}
+
+// Every recording the process knows about, keyed by the candidate id. This is what the control
+// server reads from; nothing in here touches Python, so it never needs the GIL.
+#[derive(Clone, Default)]
+pub struct Registry {
+    inner: Arc<Mutex<HashMap<String, Recording>>>,
+}
+
+impl Registry {
+    pub fn add(&self, candidate: &Candidate, target: &str, rule: &str) -> RecordingHandle {
+        let now = Utc::now();
+        let recording = Recording {
+            id: candidate.id.clone(),
+            target: target.to_string(),
+            title: candidate.title.clone(),
+            channel: candidate.channel_name.clone(),
+            channel_id: candidate.channel_id.clone(),
+            rule: rule.to_string(),
+            scheduled_start: candidate.scheduled_start,
+            members_only: candidate.members_only,
+            state: RecordingState::Starting,
+            outcome: None,
+            found: now,
+            updated: now,
+            cancel: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Mutex::new(Progress::default())),
+        };
+        let handle = RecordingHandle {
+            registry: self.clone(),
+            id: recording.id.clone(),
+            cancel: recording.cancel.clone(),
+            progress: recording.progress.clone(),
+        };
+        self.inner.lock().unwrap().insert(recording.id.clone(), recording);
+        handle
+    }
+
+    pub fn list(&self) -> Vec<Recording> {
+        let mut list: Vec<Recording> = self.inner.lock().unwrap().values().cloned().collect();
+        list.sort_by_key(|r| r.found);
+        list
+    }
+
+    pub fn get(&self, id: &str) -> Option<Recording> {
+        self.inner.lock().unwrap().get(id).cloned()
+    }
+
+    // Whether an already found candidate should be recorded again. A queued target gets another go if
+    // its last recording didn't finish, e.g. it failed or was cancelled; re-queueing it is how a retry
+    // is asked for.
+    pub fn retry(&self, candidate: &Candidate) -> bool {
+        candidate.requested && self.get(&candidate.id)
+            .is_some_and(|r| r.state == RecordingState::Done && r.outcome != Some(Outcome::Finished))
+    }
+
+    // Flags a recording to stop. A download in progress is interrupted by its watchdog within a few
+    // seconds, and one that's waiting notices the next time it wakes up. Returns false if there's
+    // nothing running to cancel.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.inner.lock().unwrap().get(id) {
+            Some(recording) if recording.state != RecordingState::Done => {
+                recording.cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&self, id: &str, state: RecordingState, outcome: Option<Outcome>) {
+        if let Some(recording) = self.inner.lock().unwrap().get_mut(id) {
+            recording.state = state;
+            recording.outcome = outcome;
+            recording.updated = Utc::now();
+        }
+    }
+}
+
+// A StreamManager's link back to its registry entry.
+#[derive(Clone)]
+pub struct RecordingHandle {
+    registry: Registry,
+    pub id: String,
+    pub cancel: Arc<AtomicBool>,
+    pub progress: Arc<Mutex<Progress>>,
+}
+
+impl RecordingHandle {
+    // For a StreamManager that isn't part of a larger recorder, like the one-shot record command.
+    pub fn standalone(target: &str) -> RecordingHandle {
+        let candidate = Candidate::from_target(target);
+        Registry::default().add(&candidate, target, "manual")
+    }
+
+    pub fn set_state(&self, state: RecordingState) {
+        self.registry.update(&self.id, state, None);
+    }
+
+    pub fn finish(&self, outcome: Outcome) {
+        self.registry.update(&self.id, RecordingState::Done, Some(outcome));
+    }
+
+    pub fn recording(&self) -> Option<Recording> {
+        self.registry.get(&self.id)
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the manual queue hands over for a target.
+    fn queued(target: &str) -> Candidate {
+        Candidate::from_target(target)
+    }
+
+    #[test]
+    fn retries_queued_targets_that_did_not_finish() {
+        let registry = Registry::default();
+        for outcome in [Outcome::Failed, Outcome::Cancelled, Outcome::NotStarted, Outcome::AuthFailed] {
+            let candidate = queued("dQw4w9WgXcQ");
+            registry.add(&candidate, "dQw4w9WgXcQ", "manual").finish(outcome);
+            assert!(registry.retry(&candidate));
+        }
+    }
+
+    #[test]
+    fn does_not_retry_finished_or_running_recordings() {
+        let registry = Registry::default();
+        let candidate = queued("dQw4w9WgXcQ");
+        // Never recorded at all, so nothing to retry; the found set decides.
+        assert!(!registry.retry(&candidate));
+
+        let handle = registry.add(&candidate, "dQw4w9WgXcQ", "manual");
+        assert!(!registry.retry(&candidate));
+        handle.set_state(RecordingState::Recording);
+        assert!(!registry.retry(&candidate));
+        handle.finish(Outcome::Finished);
+        assert!(!registry.retry(&candidate));
+    }
+
+    #[test]
+    fn only_retries_queued_targets() {
+        let registry = Registry::default();
+        let mut candidate = queued("dQw4w9WgXcQ");
+        candidate.requested = false;
+        registry.add(&candidate, "dQw4w9WgXcQ", "archive").finish(Outcome::Failed);
+        assert!(!registry.retry(&candidate));
+    }
+}
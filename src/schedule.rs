@@ -0,0 +1,241 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, info};
+
+use crate::config::DiscoveryConfig;
+use crate::discovery::{Candidate, LiveStatus};
+
+// How often the discovery loop wakes up. Local sources (the manual queue) are polled every tick;
+// the rest only when the scheduler says they're due.
+pub const TICK: Duration = Duration::from_secs(10);
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+// Works out when the discovery sources that make requests are next due, from what's scheduled on our
+// channels, and keeps them within the hourly request budget.
+pub struct Scheduler {
+    config: DiscoveryConfig,
+    last_full: Option<Instant>,
+    last_priority: Option<Instant>,
+    // When each request in the last hour went out.
+    spent: VecDeque<Instant>,
+    // Scheduled starts of our channels' upcoming streams, by id.
+    starts: HashMap<String, DateTime<Utc>>,
+    // When one of our channels was last seen live. Starts out as now, since nothing's known yet.
+    active: Instant,
+    // The full poll interval last logged, to only log changes.
+    interval: Duration,
+}
+
+impl Scheduler {
+    pub fn new(config: DiscoveryConfig) -> Scheduler {
+        Scheduler {
+            interval: Duration::from_secs(config.interval_seconds),
+            config,
+            last_full: None,
+            last_priority: None,
+            spent: VecDeque::new(),
+            starts: HashMap::new(),
+            active: Instant::now(),
+        }
+    }
+
+    // Picks up edits to the config, e.g. after a reload.
+    pub fn configure(&mut self, config: DiscoveryConfig) {
+        self.config = config;
+    }
+
+    // The time between full polls right now. Anything scheduled soon also caps it, so the poll just
+    // before a start isn't missed by sleeping through it. Polls only slow down to idle_seconds once our
+    // channels have gone quiet, with nothing live for idle_hours and nothing scheduled for as long,
+    // since unscheduled streams can start at any time during the day.
+    fn full_interval(&mut self) -> Duration {
+        let now = Utc::now();
+        let window = chrono::Duration::minutes(self.config.near_start_minutes as i64);
+        self.starts.retain(|_, start| *start + window > now);
+        let near = Duration::from_secs(self.config.near_start_seconds);
+        let quiet = self.active.elapsed() >= Duration::from_secs(self.config.idle_hours * 60 * 60);
+        let usual = |idle: bool| match idle {
+            true => Duration::from_secs(self.config.idle_seconds),
+            false => Duration::from_secs(self.config.interval_seconds),
+        };
+        let interval = match self.starts.values().min() {
+            Some(start) if *start - window <= now => near,
+            Some(start) => {
+                let usual = usual(quiet && *start - chrono::Duration::hours(self.config.idle_hours as i64) > now);
+                let until = (*start - window - now).to_std().unwrap_or_default();
+                cmp::max(cmp::min(usual, until), near)
+            }
+            None => usual(quiet),
+        };
+        // Nothing's known before the first poll, so there's nothing to say until then.
+        if interval != self.interval && self.last_full.is_some() {
+            match self.starts.values().min() {
+                Some(start) => info!("Polling every {}s, next scheduled start {}.", interval.as_secs(), start),
+                None => info!("Polling every {}s, nothing scheduled.", interval.as_secs()),
+            }
+            self.interval = interval;
+        }
+        interval
+    }
+
+    // Whether a full poll is due this tick.
+    pub fn full_due(&mut self) -> bool {
+        let interval = self.full_interval();
+        self.last_full.is_none_or(|last| last.elapsed() >= interval)
+    }
+
+    // Whether the priority channels are due their own poll this tick.
+    pub fn priority_due(&self) -> bool {
+        !self.config.priority_channels.is_empty() && self.last_priority
+            .is_none_or(|last| last.elapsed() >= Duration::from_secs(self.config.priority_seconds))
+    }
+
+    // Books requests against the hourly budget, or turns them down if there isn't room.
+    pub fn spend(&mut self, requests: u32) -> bool {
+        while self.spent.front().is_some_and(|at| at.elapsed() >= HOUR) {
+            self.spent.pop_front();
+        }
+        if self.spent.len() + requests as usize > self.config.hourly_budget as usize {
+            debug!("Hourly request budget of {} used up, holding off.", self.config.hourly_budget);
+            return false;
+        }
+        self.spent.extend((0..requests).map(|_| Instant::now()));
+        true
+    }
+
+    pub fn polled(&mut self, full: bool, priority: bool) {
+        if full {
+            self.last_full = Some(Instant::now());
+        }
+        if priority {
+            self.last_priority = Some(Instant::now());
+        }
+    }
+
+    // Keeps track of when our channels' streams are scheduled to start, and when they were last live.
+    pub fn seen(&mut self, candidate: &Candidate, ours: bool) {
+        if ours && candidate.status == LiveStatus::Live {
+            self.active = Instant::now();
+        }
+        match candidate.scheduled_start {
+            Some(start) if ours && candidate.status == LiveStatus::Upcoming => {
+                self.starts.insert(candidate.id.clone(), start);
+            }
+            _ => {
+                self.starts.remove(&candidate.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upcoming(id: &str, in_minutes: i64) -> Candidate {
+        let mut candidate = Candidate::from_target(id);
+        candidate.status = LiveStatus::Upcoming;
+        candidate.scheduled_start = Some(Utc::now() + chrono::Duration::minutes(in_minutes));
+        candidate
+    }
+
+    fn ago(duration: Duration) -> Instant {
+        Instant::now() - duration
+    }
+
+    #[test]
+    fn spends_within_the_hourly_budget() {
+        let mut scheduler = Scheduler::new(DiscoveryConfig { hourly_budget: 3, ..Default::default() });
+        assert!(scheduler.spend(2));
+        assert!(!scheduler.spend(2));
+        assert!(scheduler.spend(1));
+        assert!(!scheduler.spend(1));
+        assert!(scheduler.spend(0));
+
+        // Requests stop counting an hour after they went out.
+        scheduler.spent[0] = ago(HOUR);
+        assert!(scheduler.spend(1));
+        assert!(!scheduler.spend(1));
+    }
+
+    #[test]
+    fn full_polls_are_due_each_interval() {
+        let mut scheduler = Scheduler::new(DiscoveryConfig::default());
+        assert!(scheduler.full_due());
+        scheduler.polled(true, false);
+        assert!(!scheduler.full_due());
+        scheduler.last_full = Some(ago(Duration::from_secs(119)));
+        assert!(!scheduler.full_due());
+        scheduler.last_full = Some(ago(Duration::from_secs(120)));
+        assert!(scheduler.full_due());
+    }
+
+    #[test]
+    fn priority_polls_need_priority_channels() {
+        let mut scheduler = Scheduler::new(DiscoveryConfig::default());
+        assert!(!scheduler.priority_due());
+
+        let config = DiscoveryConfig { priority_channels: vec![String::from("UC123")], ..Default::default() };
+        scheduler.configure(config);
+        assert!(scheduler.priority_due());
+        scheduler.polled(false, true);
+        assert!(!scheduler.priority_due());
+        scheduler.last_priority = Some(ago(Duration::from_secs(60)));
+        assert!(scheduler.priority_due());
+    }
+
+    #[test]
+    fn polls_faster_around_scheduled_starts() {
+        let mut scheduler = Scheduler::new(DiscoveryConfig::default());
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+
+        // Someone else's stream doesn't count.
+        scheduler.seen(&upcoming("aaaaaaaaaaa", 5), false);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+
+        // Far off, so polling as usual, until it gets close enough to cut the wait short.
+        scheduler.seen(&upcoming("bbbbbbbbbbb", 60), true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+        scheduler.seen(&upcoming("bbbbbbbbbbb", 11), true);
+        let interval = scheduler.full_interval();
+        assert!(interval > Duration::from_secs(30) && interval <= Duration::from_secs(60), "{:?}", interval);
+
+        // Within near_start_minutes either side of the start.
+        scheduler.seen(&upcoming("bbbbbbbbbbb", 5), true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(30));
+        scheduler.seen(&upcoming("bbbbbbbbbbb", -5), true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(30));
+        scheduler.seen(&upcoming("bbbbbbbbbbb", -11), true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+
+        // Once it's live it's no longer waited on.
+        scheduler.seen(&upcoming("ccccccccccc", 5), true);
+        let mut live = upcoming("ccccccccccc", 5);
+        live.status = LiveStatus::Live;
+        scheduler.seen(&live, true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn slows_down_once_quiet() {
+        let mut scheduler = Scheduler::new(DiscoveryConfig::default());
+        scheduler.active = ago(Duration::from_secs(3 * 60 * 60));
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(600));
+
+        // Not quiet with something coming up within idle_hours.
+        scheduler.seen(&upcoming("aaaaaaaaaaa", 2 * 60), true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+        scheduler.seen(&upcoming("aaaaaaaaaaa", 4 * 60), true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(600));
+
+        // Or with one of our channels live.
+        let mut live = Candidate::from_target("bbbbbbbbbbb");
+        live.status = LiveStatus::Live;
+        scheduler.seen(&live, true);
+        assert_eq!(scheduler.full_interval(), Duration::from_secs(120));
+    }
+}
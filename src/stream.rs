@@ -1,21 +1,229 @@
-use std::{cmp, thread, time};
+// The pyo3 macro expansion trips this lint on every #[pymethods] function.
+#![allow(clippy::useless_conversion)]
+
+use std::{cmp, fmt, fs, thread, time};
+use std::time::Instant;
 use std::error::Error;
+use std::os::raw::{c_long, c_ulong};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use chrono::{DateTime, Utc};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::ffi;
 use pyo3::prelude::*;
 use pyo3::types::{PyBool, PyDict, PyList, PyTuple};
-use tokio::runtime::Runtime;
-use tokio::task;
-use tracing::{debug, error, info, Level, span, subscriber, warn};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{error, info, warn};
+
+use crate::api_handler::{self, DexClient, Video, YouTubeClient, YouTubeError};
+use crate::chat::{self, ChatCapture};
+use crate::config::{Config, Credentials, Route};
+use crate::cookies::{self, CookieStatus};
+use crate::discovery;
+use crate::hooks::{self, Hook};
+use crate::manifest::{Manifest, Segment};
+use crate::{metrics, quota};
+use crate::notify::{self, Event, EventKind};
+use crate::registry::{Progress, RecordingHandle, RecordingState};
+use crate::watchdog::{self, Watch};
+
+// How often the progress hook writes a progress line to the log.
+const PROGRESS_LOG_INTERVAL: time::Duration = time::Duration::from_secs(300);
+
+// Where finished recordings end up.
+pub const DOWNLOAD_DIR: &str = "downloads";
+
+// Where yt-dlp writes files while they're being downloaded, before moving them into DOWNLOAD_DIR.
+// Set as "active" in get_dict, which yt-dlp takes relative to the home path.
+pub const TEMP_DIR: &str = "downloads/active";
+
+// Files named after a given video id, which the default output template puts in brackets at the end
+// of the name. Only for what can't be found through the manifest; see Manifest::files.
+pub fn recording_files(id: &str) -> Vec<PathBuf> {
+    files_tagged(DOWNLOAD_DIR, id)
+}
+
+// Files yt-dlp is still writing for a given video id.
+pub fn active_files(id: &str) -> Vec<PathBuf> {
+    files_tagged(TEMP_DIR, id)
+}
+
+fn files_tagged(dir: &str, id: &str) -> Vec<PathBuf> {
+    let tag = format!("[{}]", id);
+    match fs::read_dir(dir) {
+        Ok(dir) => {
+            dir.filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().contains(&tag)))
+                .collect()
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+// yt-dlp wraps a GeoRestrictedError in a DownloadError, so only the message is left to go on.
+fn geo_restricted(err: &str) -> bool {
+    ["geo restriction", "available in your country", "not available from your location"].iter()
+        .any(|text| err.contains(text))
+}
+
+// The same goes for a video that was taken down, which otherwise looks like any other failure. A bare
+// "Video unavailable" isn't enough to go on, since YouTube also says that when it just wants a retry
+// ("This content isn't available, try again later"), so only the reasons after it count.
+fn removed(err: &str) -> Option<PostStatus> {
+    if ["Private video", "This video is private"].iter().any(|text| err.contains(text)) {
+        Some(PostStatus::Private)
+    } else if ["This video has been removed", "has been terminated", "This video is no longer available"].iter()
+        .any(|text| err.contains(text)) {
+        Some(PostStatus::Deleted)
+    } else {
+        None
+    }
+}
+
+// What other sites (Twitch mostly) say once a stream is over, or hasn't started.
+fn not_live(err: &str) -> bool {
+    ["not currently live", "is offline", "is not live"].iter().any(|text| err.contains(text))
+}
+
+// Sorts a yt-dlp error message the same way error_check does, for the metrics.
+fn error_kind(err: &str) -> &'static str {
+    if geo_restricted(err) {
+        return "geo_restricted";
+    }
+    if removed(err).is_some() {
+        return "removed";
+    }
+    if not_live(err) {
+        return "offline";
+    }
+    match err.rsplit(' ').next().unwrap_or_default() {
+        "moments." | "shortly" | "minutes." | "minutes" | "hours." | "hours" | "days." | "days" | "years."
+        | "years" => "upcoming",
+        "perks." => "members_only",
+        "difficulties." | "difficulties" => "offline",
+        "later." | "later" => "unavailable",
+        _ => "unknown",
+    }
+}
 
-use crate::api_handler;
+// Where a stream stands upstream once yt-dlp has returned from it, as opposed to discovery::LiveStatus,
+// which is what a source said about it beforehand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PostStatus {
+    Live,
+    Upcoming,
+    Ended,
+    // Taken private. Anyone but the owner sees it vanish, same as a deletion, so this is only known
+    // when yt-dlp says so.
+    Private,
+    // Deleted, or gone from the API for some other reason.
+    Deleted,
+}
+
+impl fmt::Display for PostStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostStatus::Live => write!(f, "live"),
+            PostStatus::Upcoming => write!(f, "upcoming"),
+            PostStatus::Ended => write!(f, "ended"),
+            PostStatus::Private => write!(f, "privated"),
+            PostStatus::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+impl PostStatus {
+    // Reads a videos.list item. Being gone outranks everything else, and an end time outranks
+    // liveBroadcastContent, which has been seen saying "live" for a while after the stream ended.
+    fn from_video(video: &Video) -> Result<PostStatus, String> {
+        let status = video.status.as_ref();
+        let upload = status.and_then(|s| s.upload_status.as_deref());
+        if matches!(upload, Some("deleted" | "failed" | "rejected")) {
+            return Ok(PostStatus::Deleted);
+        }
+        if status.and_then(|s| s.privacy_status.as_deref()) == Some("private") {
+            return Ok(PostStatus::Private);
+        }
+        if video.live_streaming_details.as_ref().is_some_and(|d| d.actual_end_time.is_some()) {
+            return Ok(PostStatus::Ended);
+        }
+        match video.snippet.as_ref().and_then(|s| s.live_broadcast_content.as_deref()) {
+            Some("live") => Ok(PostStatus::Live),
+            Some("upcoming") => Ok(PostStatus::Upcoming),
+            Some("none") => Ok(PostStatus::Ended),
+            Some(other) => Err(format!("Unexpected liveBroadcastContent: {}", other)),
+            None => Err(String::from("Google API returned no liveBroadcastContent.")),
+        }
+    }
+}
+
+// How a download loop ended. Mostly matters for the one-shot record command, which turns this into
+// an exit code.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Finished,
+    NotStarted,
+    AuthFailed,
+    Failed,
+    Cancelled,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Finished => write!(f, "finished"),
+            Outcome::NotStarted => write!(f, "not_started"),
+            Outcome::AuthFailed => write!(f, "auth_failed"),
+            Outcome::Failed => write!(f, "failed"),
+            Outcome::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl Outcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Finished => 0,
+            Outcome::Failed => 1,
+            Outcome::NotStarted => 3,
+            Outcome::AuthFailed => 4,
+            Outcome::Cancelled => 5,
+        }
+    }
+}
 
 pub struct StreamManager {
     yt_dlp: PyObject,
     opts: Py<PyDict>,
     target: String,
-    complete: bool,
+    outcome: Option<Outcome>,
+    wait: bool,
     yt_error: PyObject,
     hook_struct: Py<PyStruct>,
+    handle: RecordingHandle,
+    config: Config,
+    // The credentials to try for a members-only stream, in order, and which of them is loaded.
+    credentials: Vec<Credentials>,
+    credential: Option<usize>,
+    // When the loaded cookies had last changed as of the current YoutubeDL being made, which is the
+    // only time yt-dlp reads them.
+    cookies_read: Option<time::SystemTime>,
+    // Copied from the options on every rebuild, for the chat capture.
+    chat_options: Arc<Mutex<Py<PyDict>>>,
+    // The proxy and source address for this recording's channel or rule. The proxy is swapped for the
+    // geo proxy if the stream turns out to be geo-restricted.
+    route: Route,
+    geo_retried: bool,
+    watch: Arc<Watch>,
+    // Which output file the download is on; bumped whenever a download has to be restarted
+    // mid-stream.
+    part: u32,
+    manifest: Manifest,
 }
 
 #[pyclass]
@@ -24,17 +232,36 @@ struct PyStruct {
     pub is_upcoming: bool,
     pub is_live: bool,
     pub was_live: bool,
+    progress: Arc<Mutex<Progress>>,
+    cancel: Arc<AtomicBool>,
+    target: String,
+    last_log: Option<Instant>,
+    // Called with the first progress report, i.e. once data is actually coming in.
+    on_start: Option<Box<dyn FnOnce() + Send>>,
+}
+
+// Pulls a value out of a hook dict, treating a missing key, a None, or the wrong type all as "not
+// reported".
+fn get_value<'py, T: FromPyObject<'py>>(dict: &Bound<'py, PyDict>, key: &str) -> Option<T> {
+    dict.get_item(key).ok().flatten()
+        .filter(|val| !val.is_none())
+        .and_then(|val| val.extract::<T>().ok())
 }
 
 #[pymethods]
 impl PyStruct {
     // This feels incredibly hacky, but yt-dlp intentionally hides the info_dict
     // There may be util methods to access things, but this adds a lot of options for future development.
-    #[pyo3(signature = (* args, * * kwargs))]
+    #[pyo3(signature = (* args, * * _kwargs))]
     fn hook(&mut self,
             _py: Python<'_>,
             args: &Bound<'_, PyTuple>,
-            kwargs: Option<&Bound<'_, PyDict>>, ) -> PyResult<()> {
+            _kwargs: Option<&Bound<'_, PyDict>>, ) -> PyResult<()> {
+        // Raising from inside a hook is the only way to stop a download that's already going.
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(PyRuntimeError::new_err("Recording cancelled."));
+        }
+
         let dict = match args.get_item(0) {
             Ok(val) => {
                 val.downcast_into::<PyDict>()?
@@ -45,6 +272,55 @@ impl PyStruct {
             }
         };
 
+        // Copied out into the shared snapshot so nothing else has to take the GIL to read it. The
+        // lock is only held for the copy itself.
+        {
+            let mut progress = self.progress.lock().unwrap();
+            progress.status = get_value(&dict, "status");
+            progress.updated = Some(Utc::now());
+            if progress.started.is_none() {
+                if let Some(on_start) = self.on_start.take() {
+                    on_start();
+                }
+            }
+            progress.started = progress.started.or(progress.updated);
+            // Everything yt-dlp says it has written, so the files can be found by name later rather
+            // than by guessing at the output template.
+            let mut written: Vec<String> = get_value(&dict, "filename").into_iter().collect();
+            if let Some(info) = dict.get_item("info_dict")? {
+                if let Ok(status) = info.get_item("live_status").and_then(|s| s.extract::<String>()) {
+                    progress.live_status = Some(status);
+                }
+                if let Ok(info) = info.downcast_into::<PyDict>() {
+                    written.extend(get_value::<String>(&info, "infojson_filename"));
+                    let thumbnails: Vec<Bound<PyDict>> = get_value(&info, "thumbnails").unwrap_or_default();
+                    written.extend(thumbnails.iter().filter_map(|t| get_value::<String>(t, "filepath")));
+                }
+            }
+            for file in written {
+                if !progress.files.contains(&file) {
+                    progress.files.push(file);
+                }
+            }
+            // Not every downloader reports every field (live HLS has no total, ffmpeg reports almost
+            // nothing), so anything missing keeps its last value.
+            progress.downloaded_bytes = get_value(&dict, "downloaded_bytes").or(progress.downloaded_bytes);
+            progress.total_bytes = get_value(&dict, "total_bytes")
+                .or(get_value(&dict, "total_bytes_estimate"))
+                .or(progress.total_bytes);
+            progress.speed = get_value(&dict, "speed").or(progress.speed);
+            progress.elapsed = get_value(&dict, "elapsed").or(progress.elapsed);
+            progress.fragment_index = get_value(&dict, "fragment_index").or(progress.fragment_index);
+            progress.fragment_count = get_value(&dict, "fragment_count").or(progress.fragment_count);
+            progress.filename = get_value(&dict, "filename").or(progress.filename.take());
+
+            // Hooks fire per fragment, which is far too often to log every time.
+            if self.last_log.is_none_or(|last| last.elapsed() >= PROGRESS_LOG_INTERVAL) {
+                self.last_log = Some(Instant::now());
+                info!("{}: {}", self.target, progress);
+            }
+        }
+
         // May need to check 'was_live' and/or 'live_status' to ensure it doesn't stop halfway 
         // through a download of a non-live video 
         if dict.get_item("status")?.unwrap().eq("finished")? {
@@ -82,11 +358,11 @@ impl PyStruct {
 
     // Somewhat redundant with the hook function, but this sets stuff up early and can be expanded.
     // Would be nice to ensure this is only called once, at the beginning.
-    #[pyo3(signature = (* args, * * kwargs))]
+    #[pyo3(signature = (* args, * * _kwargs))]
     fn pre_filter(&mut self,
                   _py: Python<'_>,
                   args: &Bound<'_, PyTuple>,
-                  kwargs: Option<&Bound<'_, PyDict>>, ) -> PyResult<()> {
+                  _kwargs: Option<&Bound<'_, PyDict>>, ) -> PyResult<()> {
         //kwargs seems to have a dict of {"incomplete" : bool}
 
         let dict = match args.get_item(0) {
@@ -107,13 +383,18 @@ impl PyStruct {
         self.is_live = dict.get_item("is_live")?.unwrap().downcast_exact::<PyBool>()?.is_true();
         self.was_live = dict.get_item("was_live")?.unwrap().downcast_exact::<PyBool>()?.is_true();
 
+        let mut progress = self.progress.lock().unwrap();
+        progress.youtube = self.yt_bool;
+        progress.video_id = dict.get_item("id")?.and_then(|id| id.extract::<String>().ok());
+        progress.live_status = dict.get_item("live_status")?.and_then(|s| s.extract::<String>().ok());
+
         Ok(())
     }
 }
 
 // TODO: Check what Miri carves in the desk.
 impl StreamManager {
-    pub fn new(mut target: String) -> Result<StreamManager, Box<dyn Error>> {
+    pub fn new(mut target: String, handle: RecordingHandle, config: &Config) -> Result<StreamManager, Box<dyn Error>> {
         // Most targets will be in the proper 11 character video id format, but this cleans up
         // values for testing, isolated usage, and future piped sources.
         // Since "twitch.tv/" is 10 characters, it's very unlikely (but not impossible, with url
@@ -122,7 +403,19 @@ impl StreamManager {
             target = target.split_off(target.len() - 11);
         }
 
-        Python::with_gil(|py| {
+        let mut manifest = Manifest::load_or_new(&handle.id);
+        let mut members_only = false;
+        if let Some(recording) = handle.recording() {
+            members_only = recording.members_only;
+            manifest.target = recording.target;
+            manifest.title = recording.title;
+            manifest.channel = recording.channel;
+            manifest.channel_id = recording.channel_id;
+            manifest.rule = recording.rule;
+        }
+
+        let mut manager = Python::with_gil(|py| -> Result<StreamManager, Box<dyn Error>> {
+            watchdog::track_processes(py);
             let py_list = PyList::empty_bound(py);
             let params = PyDict::new_bound(py);
             let opts = Self::get_dict(py);
@@ -131,6 +424,11 @@ impl StreamManager {
                 is_upcoming: false,
                 is_live: false,
                 was_live: false,
+                progress: handle.progress.clone(),
+                cancel: handle.cancel.clone(),
+                target: target.clone(),
+                last_log: None,
+                on_start: Some(Self::on_start(&manifest, config)),
             })?;
 
             py_list.append(hook_struct.getattr(py, "hook")?.to_object(py))?;
@@ -142,10 +440,51 @@ impl StreamManager {
                 yt_dlp: Self::get_yt(py)?.call_bound(py, (), Some(&params))?,
                 opts,
                 target,
-                complete: false,
+                outcome: None,
+                wait: true,
                 yt_error: Self::get_err_base(py),
                 hook_struct,
+                handle,
+                config: config.clone(),
+                credentials: config.credentials_for(&manifest.channel_id),
+                credential: None,
+                cookies_read: None,
+                chat_options: Arc::new(Mutex::new(PyDict::new_bound(py).unbind())),
+                route: config.network.route(&manifest.channel_id, &manifest.rule),
+                geo_retried: false,
+                watch: Arc::new(Watch::default()),
+                part: 1,
+                manifest,
             })
+        })?;
+
+        let route = manager.route.clone();
+        manager.set_options(&[
+            ("proxy", route.proxy.map(Value::String)),
+            ("source_address", route.source_address.map(Value::String)),
+        ])?;
+
+        // Known members-only streams get their credentials from the start, rather than after a failed
+        // attempt, starting with ones whose cookies look usable.
+        if members_only {
+            manager.credentials.sort_by_key(|c| !cookies::check(&c.cookies).usable());
+            manager.use_credentials(0)?;
+        }
+
+        // Carry on from the last part if this has been recorded before, rather than have
+        // nooverwrite skip straight past it.
+        manager.part = manager.manifest.last_part() + 1;
+        if manager.part > 1 {
+            manager.set_part_template();
+        }
+        Ok(manager)
+    }
+
+    fn on_start(manifest: &Manifest, config: &Config) -> Box<dyn FnOnce() + Send> {
+        let (manifest, notify, hooks) = (manifest.clone(), config.notify.clone(), config.hooks.clone());
+        Box::new(move || {
+            notify::send(&notify, Event::recording(EventKind::Started, "Recording started", &manifest, None));
+            hooks::run(&hooks, Hook::Start, &manifest, None);
         })
     }
 
@@ -165,7 +504,7 @@ impl StreamManager {
         // nested, but that's a problem for another day.
         let paths = PyDict::new_bound(py);
         paths.set_item("temp", "active").unwrap();
-        paths.set_item("home", "downloads").unwrap();
+        paths.set_item("home", DOWNLOAD_DIR).unwrap();
         dict.set_item("paths", paths).unwrap();
 
         // Notable/interesting options not used here:
@@ -199,22 +538,6 @@ impl StreamManager {
         }
     }
 
-    // Adds a filter to only download live streams.
-    // Appears fairly gentle, but actually kills the thread or something?
-    // Leaves the while loop but has an exit code 0?
-    pub fn set_live_only(&mut self) {
-        Python::with_gil(|py| {
-            self.opts.bind(py).set_item("match_filter", PyModule::import_bound(py, "yt_dlp.utils").unwrap()
-                .getattr("_utils").unwrap()
-                .call_method1("match_filter_func", ("is_live",)).unwrap()).unwrap();
-
-            let params = PyDict::new_bound(py);
-            params.set_item("params", self.opts.bind(py)).unwrap();
-
-            self.yt_dlp = Self::get_yt(py).unwrap().call_bound(py, (), Some(&params)).unwrap()
-        })
-    }
-
     // For Testing. MAY NOT WORK WITH THE COMPLETED FLAG!
     pub fn _set_skip(&mut self) {
         Python::with_gil(|py| {
@@ -232,17 +555,234 @@ impl StreamManager {
         self.target = target;
     }
 
+    // Whether to sit on an upcoming stream until it starts. On by default; the one-shot record
+    // command turns it off unless asked.
+    pub fn set_wait(&mut self, wait: bool) {
+        self.wait = wait;
+    }
+
+    // Sets an arbitrary yt-dlp option. The value goes through Python's json module, so anything that
+    // can be written as JSON (numbers, lists, dicts) ends up as the matching Python type.
+    pub fn set_option(&mut self, key: &str, value: &Value) -> PyResult<()> {
+        self.set_options(&[(key, Some(value.clone()))])
+    }
+
+    // Sets (or, for None, removes) several options with a single rebuild.
+    fn set_options(&mut self, options: &[(&str, Option<Value>)]) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let json = PyModule::import_bound(py, "json")?;
+            for (key, value) in options {
+                match value {
+                    Some(value) => self.opts.bind(py).set_item(key, json.call_method1("loads", (value.to_string(),))?)?,
+                    None if self.opts.bind(py).contains(key)? => self.opts.bind(py).del_item(key)?,
+                    None => {}
+                }
+            }
+            self.rebuild(py)
+        })
+    }
+
+    // Loads one of the channel's credentials in place of whichever were loaded before. The cookies
+    // are read fresh by the new YoutubeDL, so a replaced cookie file is picked up without a restart.
+    fn use_credentials(&mut self, index: usize) -> PyResult<()> {
+        let Some(credentials) = self.credentials.get(index).cloned() else {
+            return Ok(());
+        };
+        let (file, browser) = match cookies::option(&credentials.cookies) {
+            Some(("cookiesfrombrowser", value)) => (None, Some(value)),
+            Some((_, value)) => (Some(value), None),
+            None => (None, None),
+        };
+        let proxy = match self.geo_retried {
+            true => self.route.proxy.clone(),
+            false => credentials.proxy.or(self.route.proxy.clone()),
+        };
+        info!("{}: Using the {} credentials.", self.target, credentials.name);
+        self.credential = Some(index);
+        self.set_options(&[
+            ("cookiefile", file),
+            ("cookiesfrombrowser", browser),
+            ("proxy", proxy.map(Value::String)),
+        ])
+    }
+
+    // The YoutubeDL object copies some options on creation, so it's simplest to just make a new one
+    // whenever they change.
+    fn rebuild(&mut self, py: Python) -> PyResult<()> {
+        let params = PyDict::new_bound(py);
+        params.set_item("params", self.opts.bind(py))?;
+        self.yt_dlp = Self::get_yt(py).unwrap().call_bound(py, (), Some(&params))?;
+        self.cookies_read = self.cookies_modified();
+        let shared = PyDict::new_bound(py);
+        for key in chat::SHARED_OPTIONS {
+            if let Some(value) = self.opts.bind(py).get_item(key)? {
+                shared.set_item(key, value)?;
+            }
+        }
+        *self.chat_options.lock().unwrap() = shared.unbind();
+        Ok(())
+    }
+
+    // When the loaded credentials' cookies last changed, if any are loaded.
+    fn cookies_modified(&self) -> Option<time::SystemTime> {
+        let credentials = self.credential.and_then(|index| self.credentials.get(index))?;
+        cookies::modified(&credentials.cookies)
+    }
+
+    // Remakes the YoutubeDL if its cookies have been replaced or refreshed since it was made, so the
+    // next attempt uses the new ones.
+    fn reload_cookies(&mut self) {
+        if self.cookies_modified() == self.cookies_read {
+            return;
+        }
+        info!("{}: Cookies changed, reloading them.", self.target);
+        if let Err(err) = Python::with_gil(|py| self.rebuild(py)) {
+            error!("{}: Failed to reload cookies: {}", self.target, err);
+        }
+    }
+
+    // Sleeps before trying an upcoming stream again, or gives up on it if not set to wait.
+    fn wait_upcoming(&mut self, duration: time::Duration) {
+        if self.wait {
+            self.handle.set_state(RecordingState::Waiting);
+            self.pause(duration);
+        } else {
+            info!("{}: Stream hasn't started and not set to wait.", self.target);
+            self.outcome = Some(Outcome::NotStarted);
+        }
+    }
+
+    // Moves the download on to a new output file once a part has been written. nooverwrite would
+    // otherwise skip the download entirely, since the old file already exists.
+    fn next_part(&mut self) {
+        self.part += 1;
+        warn!("{}: Continuing download as part {}.", self.target, self.part);
+        self.set_part_template();
+    }
+
+    fn set_part_template(&mut self) {
+        Python::with_gil(|py| -> PyResult<()> {
+            let outtmpl = PyDict::new_bound(py);
+            outtmpl.set_item("default", format!("%(title)s [%(id)s] (part {}).%(ext)s", self.part))?;
+            self.opts.bind(py).set_item("outtmpl", outtmpl)?;
+            self.rebuild(py)
+        }).unwrap_or_else(|err| {
+            error!("{}: Failed to set the output template for part {}: {}", self.target, self.part, err);
+        });
+    }
+
+    // The attempt that just ended, if it got as far as writing anything. Only the progress hook sets
+    // the update time, so anything newer than the attempt start means data came in.
+    fn attempt_segment(&self, started: DateTime<Utc>) -> Option<Segment> {
+        let progress = self.handle.progress.lock().unwrap();
+        if progress.updated? < started {
+            return None;
+        }
+        Some(Segment {
+            part: self.part,
+            file: progress.filename.clone(),
+            start: started,
+            end: progress.updated,
+            bytes: progress.downloaded_bytes,
+            ended: String::new(),
+        })
+    }
+
+    // Brings the manifest up to date with the files yt-dlp has written so far, and the id it named
+    // them after.
+    fn note_files(&mut self) {
+        let progress = self.handle.progress.lock().unwrap();
+        let mut changed = progress.video_id.is_some() && progress.video_id != self.manifest.video_id;
+        if changed {
+            self.manifest.video_id = progress.video_id.clone();
+        }
+        for file in &progress.files {
+            if !self.manifest.files.contains(file) {
+                self.manifest.files.push(file.clone());
+                changed = true;
+            }
+        }
+        drop(progress);
+        if changed {
+            self.manifest.save();
+        }
+    }
+
+    // Sleeps a second at a time so a cancel doesn't have to wait out a six-hour nap.
+    fn pause(&self, duration: time::Duration) {
+        let mut remaining = duration;
+        while !remaining.is_zero() && !self.handle.cancelled() {
+            let step = cmp::min(remaining, time::Duration::from_secs(1));
+            thread::sleep(step);
+            remaining -= step;
+        }
+    }
+
     // Core loop. This is basically a finite state machine with only a couple of core states; it's
     // the "unexpected" handling that adds all the extra complexity.
-    pub fn download_loop(&mut self) {
-        while !self.complete {
-            match Python::with_gil(|py| {
-                self.yt_dlp.call_method_bound(py, "download", (&self.target,), None)
-            }) {
+    pub fn download_loop(&mut self) -> Outcome {
+        let thread_id = Python::with_gil(|py| -> PyResult<c_long> {
+            PyModule::import_bound(py, "threading")?.call_method0("get_ident")?.extract::<c_ulong>()
+                .map(|id| id as c_long)
+        });
+        let thread_id = match thread_id {
+            Ok(thread_id) => Some(thread_id),
+            Err(err) => {
+                error!("{}: Failed to get thread id, watchdog disabled: {}", self.target, err);
+                None
+            }
+        };
+        if let Some(thread_id) = thread_id {
+            let stall = self.config.watchdog.enabled
+                .then(|| time::Duration::from_secs(self.config.watchdog.stall_seconds));
+            watchdog::spawn(self.target.clone(), thread_id, self.watch.clone(), self.handle.clone(), stall);
+        }
+        let chat = self.config.chat.enabled
+            .then(|| ChatCapture::spawn(self.target.clone(), self.handle.clone(), self.config.chat.clone(),
+                self.chat_options.clone()));
+
+        while self.outcome.is_none() {
+            if self.handle.cancelled() {
+                info!("{}: Recording cancelled.", self.target);
+                self.outcome = Some(Outcome::Cancelled);
+                break;
+            }
+            self.reload_cookies();
+            self.handle.set_state(RecordingState::Recording);
+            let started = Utc::now();
+            *self.watch.attempt_started.lock().unwrap() = Some(started);
+            let result = Python::with_gil(|py| {
+                self.watch.downloading.store(true, Ordering::Relaxed);
+                let res = self.yt_dlp.call_method_bound(py, "download", (&self.target,), None);
+                self.watch.downloading.store(false, Ordering::Relaxed);
+                let interrupted = self.watch.stalled.load(Ordering::Relaxed)
+                    || self.watch.cancelled.load(Ordering::Relaxed);
+                if let (true, Some(thread_id)) = (interrupted, thread_id) {
+                    // The download may have ended on its own before the interrupt landed; this
+                    // makes sure it doesn't go off somewhere else later.
+                    unsafe { ffi::PyThreadState_SetAsyncExc(thread_id, std::ptr::null_mut()); }
+                }
+                res
+            });
+            let segment = self.attempt_segment(started);
+            self.note_files();
+            let stalled = self.watch.stalled.swap(false, Ordering::Relaxed);
+
+            match result {
+                // Whatever the download ended with, stopping it was the point.
+                _ if self.handle.cancelled() => {
+                    info!("{}: Recording cancelled.", self.target);
+                    self.outcome = Some(Outcome::Cancelled);
+                }
                 Ok(_res) => {
-                    info!("{}: Download attempt ended without error.", self.target);
+                    info!("{}: Download attempt ended without error ({}).", self.target,
+                        self.handle.progress.lock().unwrap());
                     self.post_check();
                 }
+                Err(_) if stalled => {
+                    metrics::YTDLP_ERRORS.inc(&[("kind", "stall")]);
+                    // Handled below with the rest of the part bookkeeping.
+                }
                 Err(err) => {
                     if Python::with_gil(|py| -> bool {
                         //TODO: Check for more precise error types.
@@ -251,11 +791,63 @@ impl StreamManager {
                         self.error_check(err)
                     } else {
                         error!("{}: Download attempt encountered an unexpected error: {}", self.target, err);
-                        self.complete = true;
+                        metrics::YTDLP_ERRORS.inc(&[("kind", "unexpected")]);
+                        self.outcome = Some(Outcome::Failed);
                     }
                 }
             }
+
+            if let Some(mut segment) = segment {
+                segment.ended = match &self.outcome {
+                    None if stalled => String::from("stall"),
+                    None => String::from("retry"),
+                    Some(outcome) => outcome.to_string(),
+                };
+                self.manifest.add_segment(segment);
+                if self.outcome.is_none() {
+                    self.next_part();
+                }
+            } else if stalled && self.outcome.is_none() {
+                self.next_part();
+            }
+        }
+        self.watch.stop.store(true, Ordering::Relaxed);
+        if let Some(chat) = chat {
+            chat.stop();
+        }
+
+        if self.manifest.high_value {
+            self.manifest.keep_files();
+        }
+        if self.outcome == Some(Outcome::Finished) && self.config.concat.enabled
+            && self.manifest.segments.len() > 1 {
+            if let Err(err) = self.manifest.concatenate(&self.config.concat) {
+                error!("{}: Failed to join parts: {}", self.target, err);
+            }
+        }
+        let outcome = self.outcome.clone().unwrap();
+        self.handle.finish(outcome.clone());
+        let event = match outcome {
+            Outcome::Finished if self.manifest.high_value => Some(Event::recording(EventKind::Finished,
+                "Stream taken down mid-recording", &self.manifest, Some(&format!("The stream was {}; kept {} part(s).",
+                    self.manifest.ended.as_deref().unwrap_or("removed"), self.manifest.segments.len())))),
+            Outcome::Finished => Some(Event::recording(EventKind::Finished, "Recording finished", &self.manifest,
+                Some(&format!("{} part(s)", self.manifest.segments.len())))),
+            Outcome::Failed => Some(Event::recording(EventKind::Failed, "Recording failed", &self.manifest, None)),
+            Outcome::AuthFailed => Some(Event::recording(EventKind::AuthFailed,
+                "Membership authentication failed", &self.manifest, None)),
+            Outcome::NotStarted | Outcome::Cancelled => None,
+        };
+        if let Some(event) = event {
+            notify::send(&self.config.notify, event);
+        }
+        match outcome {
+            Outcome::Finished => hooks::run(&self.config.hooks, Hook::Finish, &self.manifest, Some(outcome.clone())),
+            Outcome::Failed | Outcome::AuthFailed =>
+                hooks::run(&self.config.hooks, Hook::Fail, &self.manifest, Some(outcome.clone())),
+            Outcome::NotStarted | Outcome::Cancelled => {}
         }
+        outcome
     }
 
     fn error_check(&mut self, err: PyErr) {
@@ -267,6 +859,19 @@ impl StreamManager {
         // UserNotLive (Not clear if this is the error yt uses for this situation)
         // DownloadError (default error?)
         let temp = err.to_string();
+        metrics::YTDLP_ERRORS.inc(&[("kind", error_kind(&temp))]);
+        if geo_restricted(&temp) {
+            self.geo_retry(&temp);
+            return;
+        }
+        if let Some(status) = removed(&temp) {
+            self.lost(status);
+            return;
+        }
+        if not_live(&temp) && discovery::youtube_id(&self.target).is_none() {
+            self.went_offline();
+            return;
+        }
         let mut err_msg = temp.rsplit(' ');
         // ends_with would be nice, but we need the preceding value as well
         match err_msg.next().unwrap() {
@@ -274,59 +879,199 @@ impl StreamManager {
                 // Should be starting soon, retry often.
                 // In the future, may want to have this value start to increase after a certain period,
                 // for circumstances where the streamer leaves the stream in limbo.
-                thread::sleep(time::Duration::from_secs(15));
+                self.wait_upcoming(time::Duration::from_secs(15));
             }
             "minutes." | "minutes" => {
                 // Try again in half the duration or 5 minutes, whatever is sooner, in case the
                 // streamer starts early/moves the time forward.
                 info!("{}: {}", self.target, err);
-                thread::sleep(time::Duration::from_secs(
+                self.wait_upcoming(time::Duration::from_secs(
                     cmp::min(err_msg.next().unwrap().parse::<u64>().unwrap() * 30, 300)));
             }
             "hours." | "hours" => {
                 // Try again in an hour. Hour based moves are probably the most common time change,
                 // so this minimizes chance of missing a start time change without adding many calls.
                 info!("{}: {}", self.target, err);
-                thread::sleep(time::Duration::from_secs(60 * 60));
+                self.wait_upcoming(time::Duration::from_secs(60 * 60));
             }
             "days." | "days" => {
                 // Try again in 6 hours. Most streams aren't set this far in advanced unless it's
                 // big enough the time is fairly set, but we're covering bases.
                 info!("{}: {}", self.target, err);
-                thread::sleep(time::Duration::from_secs(60 * 60 * 6));
+                self.wait_upcoming(time::Duration::from_secs(60 * 60 * 6));
             }
             "years." | "years" => {
                 // Generally just chat rooms, may not be worth even trying to continue. Checking once
                 // a day, just in case.
                 info!("{}: {}", self.target, err);
-                thread::sleep(time::Duration::from_secs(60 * 60 * 24));
+                self.wait_upcoming(time::Duration::from_secs(60 * 60 * 24));
             }
-            // Member video
-            // TODO: Add browser cookie support/check
+            // Member video. Each of the channel's credentials gets a turn before giving up.
             "perks." => {
                 warn!("{}: {}", self.target, err);
-                Python::with_gil(|py| {
-                    if !self.opts.bind(py).contains("cookiefile").unwrap() {
-                        self.opts.bind(py).set_item("cookiefile", "resources/cookies.txt").unwrap()
-                    } else {
-                        warn!("{}: Failed membership authentication.", self.target);
-                        self.complete = true
-                    }
-                })
+                let given = self.credential.is_none() && Python::with_gil(|py| {
+                    let opts = self.opts.bind(py);
+                    opts.contains("cookiefile").unwrap() || opts.contains("cookiesfrombrowser").unwrap()
+                });
+                if given {
+                    // Cookies passed in by hand (record -o cookiefile=...) are all there is to try.
+                    warn!("{}: Failed membership authentication with the given cookies.", self.target);
+                    self.outcome = Some(Outcome::AuthFailed);
+                    return;
+                }
+                if let Some(index) = self.credential {
+                    self.credential_failed(index);
+                }
+                let next = self.credential.map_or(0, |index| index + 1);
+                if next >= self.credentials.len() {
+                    warn!("{}: Failed membership authentication, no credentials left to try.", self.target);
+                    self.outcome = Some(Outcome::AuthFailed);
+                } else if let Err(err) = self.use_credentials(next) {
+                    error!("{}: Failed to load credentials: {}", self.target, err);
+                    self.outcome = Some(Outcome::AuthFailed);
+                }
             }
             "difficulties." | "difficulties" => {
                 // Found when a stream is offline, after having started. May be used elsewhere.
                 warn!("{}: {}", self.target, err);
-                thread::sleep(time::Duration::from_secs(15));
+                self.pause(time::Duration::from_secs(15));
+            }
+            "later." | "later" => {
+                // "Video unavailable. This content isn't available, try again later." and the like.
+                warn!("{}: {}", self.target, err);
+                self.pause(time::Duration::from_secs(30));
             }
             val => {
                 //Unknown error message.
                 error!("{}: Unsupported error message: {} (keyword: {})", self.target, err, val);
-                self.complete = true
+                notify::send(&self.config.notify, Event::recording(EventKind::Error,
+                    "Unsupported error message", &self.manifest, Some(&err.to_string())));
+                self.outcome = Some(Outcome::Failed)
             }
         }
     }
 
+    // Retries a geo-restricted stream through the geo proxy, once. A credential profile's own proxy
+    // gives way to it, since the stream can't be watched from there either.
+    fn geo_retry(&mut self, err: &str) {
+        match self.config.network.geo_proxy.clone() {
+            Some(proxy) if !self.geo_retried => {
+                warn!("{}: {} Retrying through the geo proxy.", self.target, err);
+                self.geo_retried = true;
+                self.route.proxy = Some(proxy.clone());
+                if let Err(err) = self.set_option("proxy", &Value::String(proxy)) {
+                    error!("{}: Failed to set the geo proxy: {}", self.target, err);
+                    self.outcome = Some(Outcome::Failed);
+                }
+            }
+            Some(_) => {
+                error!("{}: Still geo-restricted through the geo proxy: {}", self.target, err);
+                self.outcome = Some(Outcome::Failed);
+            }
+            None => {
+                error!("{}: Geo-restricted, and no geo proxy set: {}", self.target, err);
+                self.outcome = Some(Outcome::Failed);
+            }
+        }
+    }
+
+    // Alerts on credentials that were turned down. Cookies that look fine on paper but still get
+    // turned down have usually been signed out or rotated by YouTube.
+    fn credential_failed(&self, index: usize) {
+        let credentials = &self.credentials[index];
+        let status = match cookies::option(&credentials.cookies) {
+            Some(_) => cookies::check(&credentials.cookies),
+            None => CookieStatus::Missing,
+        };
+        warn!("{}: Failed membership authentication with the {} credentials (cookies {}).", self.target,
+            credentials.name, status);
+        let detail = match status.usable() {
+            true => String::from("YouTube turned down the cookies; they were likely signed out."),
+            false => status.to_string(),
+        };
+        cookies::alert(&credentials.cookies, &self.config.notify, "Cookies need replacing",
+            &format!("{} ({} credentials): {}", self.target, credentials.name, detail));
+    }
+
+    // The live status from the configured fallback, for when the Google API can't be asked.
+    fn fallback_status(&self) -> Result<PostStatus, Box<dyn Error>> {
+        info!("{}: Google API unavailable, checking with {} instead.", self.target, self.config.google.fallback);
+        match self.config.google.fallback.as_str() {
+            "holodex" => {
+                let key = api_handler::holodex_key().ok_or("No HoloDex key to fall back on.")?;
+                let video = DexClient::new(key, &self.config.network)?.video(&self.target)?;
+                match video["status"].as_str() {
+                    Some("live") => Ok(PostStatus::Live),
+                    Some("upcoming") => Ok(PostStatus::Upcoming),
+                    Some("past") => Ok(PostStatus::Ended),
+                    // HoloDex can't tell private from deleted either.
+                    Some("missing") => Ok(PostStatus::Deleted),
+                    status => Err(format!("HoloDex status: {}", status.unwrap_or("unknown")).into()),
+                }
+            }
+            "ytdlp" => self.ytdlp_status(),
+            _ => Err(Box::new(quota::Exhausted { used: quota::usage().used, budget: self.config.google.daily_budget })),
+        }
+    }
+
+    // The live status as yt-dlp sees it. A video that's been taken down fails to extract, and the
+    // message says how.
+    fn ytdlp_status(&self) -> Result<PostStatus, Box<dyn Error>> {
+        // Extraction only; process=False keeps it from going anywhere near the hooks.
+        let status = Python::with_gil(|py| -> PyResult<Option<String>> {
+            let kwargs = PyDict::new_bound(py);
+            kwargs.set_item("download", false)?;
+            kwargs.set_item("process", false)?;
+            let info = self.yt_dlp.call_method_bound(py, "extract_info", (&self.target,), Some(&kwargs))?;
+            Ok(info.bind(py).get_item("live_status").ok().and_then(|s| s.extract().ok()))
+        });
+        let status = match status {
+            Ok(status) => status,
+            Err(err) => return match removed(&err.to_string()) {
+                Some(status) => Ok(status),
+                None => Err(err.into()),
+            },
+        };
+        match status.as_deref() {
+            Some("is_live") => Ok(PostStatus::Live),
+            Some("is_upcoming") => Ok(PostStatus::Upcoming),
+            Some("was_live" | "post_live" | "not_live") => Ok(PostStatus::Ended),
+            status => Err(format!("yt-dlp live_status: {}", status.unwrap_or("unknown")).into()),
+        }
+    }
+
+    // Other sites only say a stream is over by no longer having it live. That's the normal end, once
+    // something has been recorded.
+    fn went_offline(&mut self) {
+        if self.recorded() {
+            info!("{}: Stream is no longer live.", self.target);
+            self.manifest.ended = Some(PostStatus::Ended.to_string());
+            self.outcome = Some(Outcome::Finished);
+        } else {
+            info!("{}: Stream isn't live, nothing recorded.", self.target);
+            self.outcome = Some(Outcome::NotStarted);
+        }
+    }
+
+    // Whether anything of the stream has been recorded, in this run or an earlier one.
+    fn recorded(&self) -> bool {
+        self.handle.progress.lock().unwrap().updated.is_some() || !self.manifest.segments.is_empty()
+    }
+
+    // The stream was privated or deleted while it was being recorded. What was recorded is likely the
+    // only copy left, so it's kept and flagged rather than treated as a failure.
+    fn lost(&mut self, status: PostStatus) {
+        self.manifest.ended = Some(status.to_string());
+        if self.recorded() {
+            warn!("{}: Stream was {} mid-recording, keeping what was recorded.", self.target, status);
+            self.manifest.high_value = true;
+            self.outcome = Some(Outcome::Finished);
+        } else {
+            error!("{}: Stream was {} before anything was recorded.", self.target, status);
+            self.outcome = Some(Outcome::Failed);
+        }
+    }
+
     // Called after yt-dlp "successfully" returns. Ensure the video is actually done, or sets things
     // to try again/continue.
     fn post_check(&mut self) {
@@ -339,60 +1084,129 @@ impl StreamManager {
             // result in exhausting the API quota.
             info!("{}: calling Google API.", self.target);
 
-            // The crate used to call the Google API *requires* a tokio runtime for internal async
-            // calls, but it's the only place were async is used and async and threads aren't friends,
-            // so we create an abomination of a closure, which requires cloning the target for
-            // closure borrow rules. If there's any functionality refactoring in the future, this is
-            // near the top of the hit list.
-            let temp_target = self.target.clone();
-            let response = Runtime::new().unwrap()
-                .block_on(async {
-                    task::spawn_blocking(|| {
-                        api_handler::google_api(temp_target)
-                    }).await.unwrap().await
-                });
+            let response = YouTubeClient::new(&self.config.network, &self.config.google)
+                .and_then(|youtube| youtube.video(&self.target));
+            let response = match response {
+                Ok(Some(video)) => PostStatus::from_video(&video).map_err(Box::<dyn Error>::from),
+                // Private videos are hidden from API keys just like deleted ones, so yt-dlp is asked
+                // which it is.
+                Ok(None) => self.ytdlp_status(),
+                // Once the day's budget is gone, or with no key at all, something free has to do instead.
+                Err(YouTubeError::Quota(_) | YouTubeError::Key(_)) => self.fallback_status(),
+                Err(err) => Err(err.into()),
+            };
 
             match response {
-                Ok(res) => {
-                    match res.snippet.unwrap().live_broadcast_content.unwrap().as_str() {
-                        "live" => {
-                            //Video still going, continue downloading
-                            warn!("{}: Video is still live.", self.target)
-                        }
-                        "none" => {
-                            info!("{}: Video is no longer live.", self.target);
-                            self.complete = true;
-                        }
-                        "upcoming" => {
-                            // Not sure if this is even reachable given current conditions.
-                            error!("{}: Download attempt successfully finished, but is not live yet!", self.target)
-                        }
-                        err => {
-                            error!("{}: Google API returned an unexpected value for is_live: {}", self.target, err);
-                            self.complete = true;
-                        }
-                    }
+                Ok(PostStatus::Live) => {
+                    //Video still going, continue downloading
+                    warn!("{}: Video is still live.", self.target)
+                }
+                Ok(PostStatus::Ended) => {
+                    info!("{}: Video is no longer live.", self.target);
+                    self.manifest.ended = Some(PostStatus::Ended.to_string());
+                    self.outcome = Some(Outcome::Finished);
+                }
+                Ok(PostStatus::Upcoming) => {
+                    // Not sure if this is even reachable given current conditions.
+                    error!("{}: Download attempt successfully finished, but is not live yet!", self.target)
+                }
+                Ok(status) => self.lost(status),
+                Err(err) if self.recorded() => {
+                    // Ostensibly there's a problem with the API, or whatever stood in for it (or the
+                    // quota ran out with no fallback). The download itself ended cleanly, so it's taken
+                    // as the stream being over rather than a good recording being thrown away.
+                    warn!("{}: Couldn't check the live status, taking the stream as ended: {}", self.target, err);
+                    notify::send(&self.config.notify, Event::recording(EventKind::Error,
+                        "Live status check failed", &self.manifest, Some(&err.to_string())));
+                    self.outcome = Some(Outcome::Finished);
                 }
                 Err(err) => {
-                    // Ostensibly there's a problem, potentially the video was removed?
-                    error!("{}: Error calling Google API: {}", self.target, err);
-                    self.complete = true;
+                    // Nothing to keep either way, so the next attempt gets to find out.
+                    warn!("{}: Couldn't check the live status, trying again: {}", self.target, err);
+                    self.pause(time::Duration::from_secs(30));
                 }
             }
-        } else {
-            // Other sites
-            Python::with_gil(|py| {
-                if !self.opts.bind(py).contains("match_filter").unwrap() {
-                    self.set_live_only()
-                } else if Python::with_gil(|py| self.hook_struct.borrow(py).was_live) {
-                    // I don't think this is ever actually reached, but there was a very specific
-                    // set of circumstances where a YouTube video would enter an endless failing loop.
-                    // This would prevent that if YouTube wasn't checked separately via API, and
-                    // hopefully stops any similar loops from other sources.
-                    error!("{}: Reached the non-YouTube post-check with a is_live filter and a was_live status.", self.target);
-                    self.complete = true
-                }
-            })
+        } else if Python::with_gil(|py| self.hook_struct.borrow(py).was_live) {
+            // Other sites have no API to ask. A VoD means the stream is over; there was once a very
+            // specific set of circumstances where YouTube would loop on one endlessly, and this stops
+            // anything similar from other sites.
+            self.went_offline();
         }
+        // Otherwise the next attempt finds out: a stream that's still going carries on, and one that's
+        // over errors as not live, see error_check.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // A videos.list item, as the Google API returns it.
+    fn video(item: Value) -> Video {
+        serde_json::from_value(item).unwrap()
+    }
+
+    #[test]
+    fn status_from_broadcast_content() {
+        let status = |content: &str| PostStatus::from_video(&video(json!({
+            "id": "dQw4w9WgXcQ",
+            "snippet": {"liveBroadcastContent": content},
+            "status": {"privacyStatus": "public", "uploadStatus": "uploaded"},
+        })));
+        assert_eq!(status("live"), Ok(PostStatus::Live));
+        assert_eq!(status("upcoming"), Ok(PostStatus::Upcoming));
+        assert_eq!(status("none"), Ok(PostStatus::Ended));
+        assert!(status("something new").is_err());
+        assert!(PostStatus::from_video(&video(json!({"id": "dQw4w9WgXcQ"}))).is_err());
+    }
+
+    #[test]
+    fn end_time_outranks_broadcast_content() {
+        let ended = video(json!({
+            "id": "dQw4w9WgXcQ",
+            "snippet": {"liveBroadcastContent": "live"},
+            "liveStreamingDetails": {"actualEndTime": "2024-01-02T03:04:05Z"},
+        }));
+        assert_eq!(PostStatus::from_video(&ended), Ok(PostStatus::Ended));
+    }
+
+    #[test]
+    fn being_gone_outranks_everything() {
+        for upload in ["deleted", "failed", "rejected"] {
+            let gone = video(json!({
+                "id": "dQw4w9WgXcQ",
+                "snippet": {"liveBroadcastContent": "live"},
+                "status": {"privacyStatus": "private", "uploadStatus": upload},
+            }));
+            assert_eq!(PostStatus::from_video(&gone), Ok(PostStatus::Deleted));
+        }
+        let private = video(json!({
+            "id": "dQw4w9WgXcQ",
+            "snippet": {"liveBroadcastContent": "live"},
+            "liveStreamingDetails": {"actualEndTime": "2024-01-02T03:04:05Z"},
+            "status": {"privacyStatus": "private", "uploadStatus": "processed"},
+        }));
+        assert_eq!(PostStatus::from_video(&private), Ok(PostStatus::Private));
+    }
+
+    #[test]
+    fn removed_videos() {
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: Private video. Sign in if you've been granted access"),
+            Some(PostStatus::Private));
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: This video is private"), Some(PostStatus::Private));
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This video has been removed by the \
+            uploader"), Some(PostStatus::Deleted));
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This video is no longer available \
+            because the YouTube account associated with this video has been terminated."), Some(PostStatus::Deleted));
+    }
+
+    #[test]
+    fn unavailable_is_not_removed() {
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: Video unavailable"), None);
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This content isn't available, try again \
+            later."), None);
+        assert_eq!(removed("ERROR: [youtube] dQw4w9WgXcQ: This live event will begin in 5 minutes."), None);
     }
 }
@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use chrono::Utc;
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::api_handler::DexClient;
+use crate::config::VerifyConfig;
+use crate::discovery;
+use crate::ffmpeg;
+use crate::manifest::{FileCheck, Manifest, Verification, VerifyStatus, MANIFEST_DIR, VIDEO_EXTENSIONS};
+use crate::stream::DOWNLOAD_DIR;
+
+// How many of ffmpeg's error lines are kept in the manifest. A badly broken file can produce
+// thousands.
+const ERROR_LINES: usize = 5;
+
+// Checks a recording's files and stores the result in its manifest. HoloDex is only asked for the
+// stream's length if the info.json doesn't have it.
+pub fn verify(id: &str, config: &VerifyConfig, dex: Option<&DexClient>) -> Verification {
+    let mut manifest = Manifest::load_or_new(id);
+    let mut files: Vec<FileCheck> = listed_files(&manifest).into_iter()
+        .filter(|file| Manifest::locate(file).is_none())
+        .map(|file| FileCheck { file, bytes: 0, duration: None, status: VerifyStatus::Missing, message: None })
+        .collect();
+    let videos = manifest.video_files();
+    files.extend(videos.iter().map(|file| check_file(file, config.full_decode)));
+
+    let mut verification = Verification {
+        at: Utc::now(),
+        status: files.iter().map(|f| f.status).max().unwrap_or(VerifyStatus::Missing),
+        files,
+        duration: None,
+        expected: None,
+        expected_from: None,
+        message: None,
+    };
+    if verification.files.is_empty() {
+        verification.message = Some(String::from("No files found."));
+    }
+
+    let durations: Vec<f64> = verification.files.iter().filter_map(|f| f.duration).collect();
+    if !durations.is_empty() {
+        verification.duration = Some(durations.iter().sum());
+    }
+    if let Some((expected, from)) = expected_duration(id, &manifest, dex) {
+        verification.expected = Some(expected);
+        verification.expected_from = Some(from.to_string());
+        if let Some(duration) = verification.duration {
+            let short = expected - duration;
+            if short > config.tolerance_seconds {
+                verification.status = verification.status.max(VerifyStatus::Truncated);
+                verification.message = Some(format!("{:.0}s short of the stream's {:.0}s ({} gap(s) recorded).",
+                    short, expected, manifest.gaps.len()));
+            }
+        }
+    }
+
+    if verification.status == VerifyStatus::Ok {
+        info!("{}: Verified ({} file(s)).", id, verification.files.len());
+    } else {
+        warn!("{}: Verification found the recording {}: {:?}", id, verification.status, verification.message);
+    }
+    manifest.verification = Some(verification.clone());
+    manifest.save();
+    verification
+}
+
+// Every recording in the archive: those with manifests, and any older downloads without one.
+pub fn archive_ids() -> Vec<String> {
+    let mut ids = BTreeSet::new();
+    if let Ok(dir) = fs::read_dir(MANIFEST_DIR) {
+        for path in dir.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Ok(manifest) = Manifest::load(&path.file_stem().unwrap_or_default().to_string_lossy()) {
+                    ids.insert(manifest.id);
+                }
+            }
+        }
+    }
+    if let Ok(dir) = fs::read_dir(DOWNLOAD_DIR) {
+        for path in dir.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if !path.extension().is_some_and(|ext| VIDEO_EXTENSIONS.iter().any(|v| ext == *v)) {
+                continue;
+            }
+            // The id is the last bracketed part of the default output template.
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            if let Some(id) = name.rsplit_once('[').and_then(|(_, rest)| rest.split_once(']')).map(|(id, _)| id) {
+                ids.insert(id.to_string());
+            }
+        }
+    }
+    ids.into_iter().collect()
+}
+
+// Files the manifest says should exist.
+fn listed_files(manifest: &Manifest) -> Vec<String> {
+    match &manifest.concatenated {
+        Some(joined) => vec![joined.clone()],
+        None => manifest.segments.iter().filter_map(|s| s.file.clone()).collect(),
+    }
+}
+
+fn check_file(file: &Path, full_decode: bool) -> FileCheck {
+    let mut check = FileCheck {
+        file: file.to_string_lossy().to_string(),
+        bytes: fs::metadata(file).map(|m| m.len()).unwrap_or(0),
+        duration: None,
+        status: VerifyStatus::Ok,
+        message: None,
+    };
+    if check.bytes == 0 {
+        check.status = VerifyStatus::Empty;
+        return check;
+    }
+    match ffmpeg::probe_duration(file) {
+        Ok(duration) => check.duration = Some(duration),
+        Err(err) => {
+            check.status = VerifyStatus::Corrupt;
+            check.message = Some(err.to_string());
+            return check;
+        }
+    }
+    debug!("Reading through {}", file.display());
+    match ffmpeg::check(file, full_decode) {
+        Ok(errors) if errors.is_empty() => {}
+        Ok(errors) => {
+            check.status = VerifyStatus::Corrupt;
+            check.message = Some(format!("{} error(s), starting with: {}", errors.len(),
+                errors.iter().take(ERROR_LINES).cloned().collect::<Vec<String>>().join(" | ")));
+        }
+        Err(err) => {
+            check.status = VerifyStatus::Corrupt;
+            check.message = Some(err.to_string());
+        }
+    }
+    check
+}
+
+// How long the stream actually was. yt-dlp only knows this if the info.json was written after the
+// stream ended, so HoloDex is the usual source for live recordings.
+fn expected_duration(id: &str, manifest: &Manifest, dex: Option<&DexClient>) -> Option<(f64, &'static str)> {
+    for path in manifest.info_files() {
+        let duration = fs::read_to_string(&path).ok()
+            .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+            .and_then(|info| info.get("duration").and_then(Value::as_f64));
+        if let Some(duration) = duration.filter(|d| *d > 0.0) {
+            return Some((duration, "info.json"));
+        }
+    }
+
+    let dex = dex?;
+    discovery::youtube_id(id)?;
+    match dex.video(id) {
+        Ok(video) => video.get("duration").and_then(Value::as_f64)
+            .filter(|d| *d > 0.0)
+            .map(|d| (d, "holodex")),
+        Err(err) => {
+            warn!("{}: Failed to get the stream's duration from HoloDex: {:?}", id, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::cmp;
+use std::fs;
+use std::os::raw::{c_long, c_ulong};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use tracing::{debug, error, warn};
+
+use crate::registry::RecordingHandle;
+use crate::stream::{active_files, recording_files};
+
+// Raised inside the download thread when it stalls or is cancelled. Derived from BaseException (like
+// KeyboardInterrupt) so yt-dlp's "except Exception" retry handling doesn't swallow it.
+// The macro checks a pyo3 feature this crate doesn't declare, hence the allow.
+#[allow(unexpected_cfgs)]
+mod exception {
+    pyo3::create_exception!(akashic, DownloadInterrupt, pyo3::exceptions::PyBaseException);
+}
+use exception::DownloadInterrupt;
+
+// How often the watchdog looks at the progress snapshot.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// yt-dlp's ffmpeg downloader (which live HLS goes through) spends the whole download waiting on the
+// ffmpeg process, and the interrupt can't land until that returns. yt-dlp's Popen is swapped for one
+// that remembers which thread started each process, so the watchdog can stop ffmpeg as well.
+const PROCESSES: &str = r#"
+import threading
+import yt_dlp.downloader.external as external
+
+running = {}
+
+class Popen(external.Popen):
+    def __init__(self, *args, **kwargs):
+        super().__init__(*args, **kwargs)
+        self.thread = threading.get_ident()
+        running.setdefault(self.thread, set()).add(self)
+
+    def __exit__(self, *args):
+        running.get(self.thread, set()).discard(self)
+        return super().__exit__(*args)
+
+def stop(thread):
+    for process in list(running.get(thread, ())):
+        if process.poll() is None:
+            process.terminate()
+
+external.Popen = Popen
+"#;
+
+static TRACKER: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+
+// Starts keeping track of the processes yt-dlp starts. Only done once, however many times it's called.
+pub fn track_processes(py: Python) {
+    let tracker = TRACKER.get_or_try_init(py, || -> PyResult<Py<PyModule>> {
+        Ok(PyModule::from_code_bound(py, PROCESSES, "akashic_processes.py", "akashic_processes")?.unbind())
+    });
+    if let Err(err) = tracker {
+        error!("Failed to track yt-dlp's processes, stalled ffmpeg downloads can't be interrupted: {}", err);
+    }
+}
+
+// Stops any processes the thread's download is waiting on, so it gets back to running Python code.
+fn stop_processes(py: Python, thread_id: c_long) {
+    let Some(tracker) = TRACKER.get(py) else {
+        return;
+    };
+    if let Err(err) = tracker.call_method1(py, "stop", (thread_id as c_ulong,)) {
+        error!("Failed to stop the download's processes: {}", err);
+    }
+}
+
+// Shared between a download thread and its watchdog.
+#[derive(Default)]
+pub struct Watch {
+    // Only changed while holding the GIL, so the watchdog (which also holds it when interrupting)
+    // can't fire into a thread that has already left the download call.
+    pub downloading: AtomicBool,
+    pub stalled: AtomicBool,
+    pub cancelled: AtomicBool,
+    pub stop: AtomicBool,
+    pub attempt_started: Mutex<Option<DateTime<Utc>>>,
+}
+
+// Starts a watchdog for one download thread. It interrupts the download call once the recording is
+// cancelled, which the progress hook would otherwise only notice the next time it's called (at the
+// very end, for the ffmpeg downloader), and, given a stall duration, once it stalls.
+// A recording is considered stalled when it has been making progress this attempt, but then goes
+// quiet for the stall duration; the StreamManager then starts a new part. Progress is either the
+// progress hook reporting fragments of a live stream, or the recording's files growing, since the
+// ffmpeg downloader (which live HLS goes through) only calls the hook at the very end.
+// The interrupt only lands when the thread next runs Python code, so a thread blocked on a socket
+// still has to wait out the socket_timeout first. One waiting on ffmpeg has it stopped.
+pub fn spawn(target: String,
+             thread_id: c_long,
+             watch: Arc<Watch>,
+             handle: RecordingHandle,
+             stall: Option<Duration>) {
+    thread::spawn(move || {
+        // Whatever's there from earlier runs doesn't count as progress.
+        let mut tag = handle.id.clone();
+        let mut size = written(&tag);
+        let mut grew = None;
+        while !watch.stop.load(Ordering::Relaxed) {
+            thread::sleep(CHECK_INTERVAL);
+            // Files are named after yt-dlp's own id, once it has said what that is. Switching to it
+            // starts the count over, for the same reason as above.
+            let video_id = handle.progress.lock().unwrap().video_id.clone();
+            if let Some(video_id) = video_id.filter(|video_id| *video_id != tag) {
+                tag = video_id;
+                size = written(&tag);
+            }
+            // Measured between attempts too, so the tail end of the last one isn't taken for the
+            // next one making progress.
+            let now = written(&tag);
+            if now != size {
+                size = now;
+                grew = Some(Utc::now());
+            }
+            if !watch.downloading.load(Ordering::Relaxed) {
+                continue;
+            }
+            if handle.cancelled() {
+                if !watch.cancelled.load(Ordering::Relaxed) {
+                    interrupt(&target, thread_id, &watch, &watch.cancelled, "Recording cancelled, stopping download.");
+                }
+                continue;
+            }
+            let Some(stall) = stall.filter(|_| !watch.stalled.load(Ordering::Relaxed)) else {
+                continue;
+            };
+
+            // Nothing to judge until this attempt has made some progress; before that it may just
+            // be waiting for the stream to start.
+            let last = {
+                let progress = handle.progress.lock().unwrap();
+                let started = *watch.attempt_started.lock().unwrap();
+                let hook = match (progress.live_status.as_deref(), progress.status.as_deref()) {
+                    (Some("is_live"), Some("downloading")) => progress.updated,
+                    _ => None,
+                };
+                match cmp::max(hook, grew) {
+                    Some(last) if Some(last) >= started => last,
+                    _ => continue,
+                }
+            };
+            let quiet = (Utc::now() - last).to_std().unwrap_or_default();
+            if quiet >= stall {
+                interrupt(&target, thread_id, &watch, &watch.stalled,
+                    &format!("No progress for {} seconds, interrupting download.", quiet.as_secs()));
+            }
+        }
+    });
+}
+
+// Interrupts the thread's download call, noting why in the given flag.
+fn interrupt(target: &str, thread_id: c_long, watch: &Watch, reason: &AtomicBool, message: &str) {
+    Python::with_gil(|py| {
+        if !watch.downloading.load(Ordering::Relaxed) {
+            debug!("{}: Download ended before the interrupt.", target);
+            return;
+        }
+        warn!("{}: {}", target, message);
+        reason.store(true, Ordering::Relaxed);
+        // Safe as long as the exception type outlives the call, which a static type does.
+        unsafe {
+            ffi::PyThreadState_SetAsyncExc(thread_id, py.get_type_bound::<DownloadInterrupt>().as_ptr());
+        }
+        stop_processes(py, thread_id);
+    });
+}
+
+// Total size of a recording's files so far, including the ones still being written.
+fn written(id: &str) -> u64 {
+    recording_files(id).iter()
+        .chain(active_files(id).iter())
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}